@@ -0,0 +1,13 @@
+-- Your SQL goes here
+CREATE TABLE collections (
+    id INTEGER PRIMARY KEY NOT NULL,
+    user BigInt NOT NULL,
+    name TEXT NOT NULL,
+    source_url TEXT
+);
+
+CREATE TABLE collection_objects (
+    collection_id INTEGER NOT NULL REFERENCES collections(id),
+    object_id INTEGER NOT NULL REFERENCES objects(id),
+    PRIMARY KEY (collection_id, object_id)
+);
\ No newline at end of file
@@ -0,0 +1,14 @@
+-- Your SQL goes here
+CREATE TABLE jobs (
+    id INTEGER PRIMARY KEY NOT NULL,
+    user BigInt NOT NULL,
+    kind TEXT NOT NULL,
+    spec TEXT NOT NULL,
+    status TEXT NOT NULL,
+    output TEXT,
+    error TEXT,
+    created_unix BigInt NOT NULL,
+    updated_unix BigInt NOT NULL
+);
+
+CREATE INDEX jobs_status ON jobs (status);
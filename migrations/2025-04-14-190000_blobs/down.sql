@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX objects_blob_hash;
+ALTER TABLE objects DROP COLUMN blob_hash;
+DROP TABLE blobs;
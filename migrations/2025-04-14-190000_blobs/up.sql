@@ -0,0 +1,11 @@
+-- Your SQL goes here
+CREATE TABLE blobs (
+    hash TEXT PRIMARY KEY NOT NULL,
+    path TEXT NOT NULL,
+    size BigInt NOT NULL,
+    refcount INTEGER NOT NULL
+);
+
+ALTER TABLE objects ADD COLUMN blob_hash TEXT REFERENCES blobs(hash);
+
+CREATE INDEX objects_blob_hash ON objects (blob_hash);
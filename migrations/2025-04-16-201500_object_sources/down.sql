@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX objects_extractor_id;
+DROP INDEX objects_source_url;
+ALTER TABLE objects DROP COLUMN extractor_id;
+ALTER TABLE objects DROP COLUMN source_url;
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+ALTER TABLE objects ADD COLUMN source_url TEXT;
+ALTER TABLE objects ADD COLUMN extractor_id TEXT;
+
+CREATE INDEX objects_source_url ON objects (source_url);
+CREATE INDEX objects_extractor_id ON objects (extractor_id);
@@ -0,0 +1,15 @@
+-- Your SQL goes here
+CREATE TABLE object_metadata (
+    object_id INTEGER PRIMARY KEY NOT NULL REFERENCES objects(id),
+    title TEXT,
+    uploader TEXT,
+    upload_date TEXT,
+    duration DOUBLE,
+    description TEXT,
+    original_url TEXT,
+    extractor TEXT,
+    width INTEGER,
+    height INTEGER,
+    vcodec TEXT,
+    acodec TEXT
+);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE retention_limits;
+ALTER TABLE objects DROP COLUMN created_unix;
+ALTER TABLE users DROP COLUMN role;
@@ -0,0 +1,14 @@
+-- Your SQL goes here
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'user';
+
+ALTER TABLE objects ADD COLUMN created_unix BigInt NOT NULL DEFAULT 0;
+-- Everything stored so far was kept for 7 days
+UPDATE objects SET created_unix = expiry_unix - 604800;
+
+CREATE TABLE retention_limits (
+    role TEXT PRIMARY KEY NOT NULL,
+    max_days INTEGER
+);
+
+-- No limits until an admin sets one, so existing objects keep their expiry
+INSERT INTO retention_limits (role, max_days) VALUES ('user', NULL), ('admin', NULL);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE users DROP COLUMN object_count;
+ALTER TABLE users DROP COLUMN bytes_allowed;
+ALTER TABLE users DROP COLUMN bytes_used;
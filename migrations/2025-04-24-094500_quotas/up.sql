@@ -0,0 +1,8 @@
+-- Your SQL goes here
+ALTER TABLE users ADD COLUMN bytes_used BigInt NOT NULL DEFAULT 0;
+ALTER TABLE users ADD COLUMN bytes_allowed BigInt;
+ALTER TABLE users ADD COLUMN object_count INTEGER NOT NULL DEFAULT 0;
+
+UPDATE users SET
+    bytes_used = (SELECT COALESCE(SUM(size), 0) FROM objects WHERE objects.user = users.snowflake),
+    object_count = (SELECT COUNT(*) FROM objects WHERE objects.user = users.snowflake);
@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+DROP TRIGGER objects_fts_metadata_delete;
+DROP TRIGGER objects_fts_metadata_insert;
+DROP TRIGGER objects_fts_delete;
+DROP TRIGGER objects_fts_update;
+DROP TRIGGER objects_fts_insert;
+DROP TABLE objects_fts;
@@ -0,0 +1,28 @@
+-- Your SQL goes here
+-- rowid is the object id
+CREATE VIRTUAL TABLE objects_fts USING fts5(name, uploader, description);
+
+INSERT INTO objects_fts (rowid, name, uploader, description)
+SELECT objects.id, objects.name, object_metadata.uploader, object_metadata.description
+FROM objects LEFT JOIN object_metadata ON object_metadata.object_id = objects.id;
+
+CREATE TRIGGER objects_fts_insert AFTER INSERT ON objects BEGIN
+    INSERT INTO objects_fts (rowid, name) VALUES (new.id, new.name);
+END;
+
+CREATE TRIGGER objects_fts_update AFTER UPDATE OF name ON objects BEGIN
+    UPDATE objects_fts SET name = new.name WHERE rowid = new.id;
+END;
+
+CREATE TRIGGER objects_fts_delete AFTER DELETE ON objects BEGIN
+    DELETE FROM objects_fts WHERE rowid = old.id;
+END;
+
+CREATE TRIGGER objects_fts_metadata_insert AFTER INSERT ON object_metadata BEGIN
+    UPDATE objects_fts SET uploader = new.uploader, description = new.description
+    WHERE rowid = new.object_id;
+END;
+
+CREATE TRIGGER objects_fts_metadata_delete AFTER DELETE ON object_metadata BEGIN
+    UPDATE objects_fts SET uploader = NULL, description = NULL WHERE rowid = old.object_id;
+END;
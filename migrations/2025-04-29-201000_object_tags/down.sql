@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX object_tags_tag;
+DROP TABLE object_tags;
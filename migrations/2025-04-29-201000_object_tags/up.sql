@@ -0,0 +1,8 @@
+-- Your SQL goes here
+CREATE TABLE object_tags (
+    object_id INTEGER NOT NULL REFERENCES objects(id),
+    tag TEXT NOT NULL,
+    PRIMARY KEY (object_id, tag)
+);
+
+CREATE INDEX object_tags_tag ON object_tags (tag);
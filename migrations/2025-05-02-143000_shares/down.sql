@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX collection_shares_user;
+DROP INDEX object_shares_user;
+DROP TABLE collection_shares;
+DROP TABLE object_shares;
@@ -0,0 +1,19 @@
+-- Your SQL goes here
+CREATE TABLE object_shares (
+    object_id INTEGER NOT NULL REFERENCES objects(id),
+    user BigInt NOT NULL,
+    access TEXT NOT NULL,
+    created_unix BigInt NOT NULL,
+    PRIMARY KEY (object_id, user)
+);
+
+CREATE TABLE collection_shares (
+    collection_id INTEGER NOT NULL REFERENCES collections(id),
+    user BigInt NOT NULL,
+    access TEXT NOT NULL,
+    created_unix BigInt NOT NULL,
+    PRIMARY KEY (collection_id, user)
+);
+
+CREATE INDEX object_shares_user ON object_shares (user);
+CREATE INDEX collection_shares_user ON collection_shares (user);
@@ -0,0 +1,10 @@
+-- This file should undo anything in `up.sql`
+CREATE TABLE sharex_config (
+    user_id BigInt NOT NULL PRIMARY KEY REFERENCES users(snowflake),
+    json TEXT NOT NULL
+);
+
+INSERT INTO sharex_config (user_id, json)
+SELECT user, json FROM destinations WHERE is_default;
+
+DROP TABLE destinations;
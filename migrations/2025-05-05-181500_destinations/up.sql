@@ -0,0 +1,22 @@
+-- Your SQL goes here
+CREATE TABLE destinations (
+    id INTEGER PRIMARY KEY NOT NULL,
+    user BigInt NOT NULL REFERENCES users(snowflake),
+    name TEXT NOT NULL,
+    json TEXT NOT NULL,
+    is_default BOOLEAN NOT NULL DEFAULT 0,
+    created_unix BigInt NOT NULL,
+    UNIQUE (user, name)
+);
+
+-- Existing configs become their user's default destination
+INSERT INTO destinations (user, name, json, is_default, created_unix)
+SELECT
+    user_id,
+    COALESCE(CASE WHEN json_valid(json) THEN json_extract(json, '$.Name') END, 'XBackbone'),
+    json,
+    1,
+    CAST(strftime('%s', 'now') AS INTEGER)
+FROM sharex_config;
+
+DROP TABLE sharex_config;
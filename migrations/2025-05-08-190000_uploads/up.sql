@@ -0,0 +1,15 @@
+-- Your SQL goes here
+CREATE TABLE uploads (
+    id INTEGER PRIMARY KEY NOT NULL,
+    -- Cleared when the object is deleted, the remote copy outlives it
+    object_id INTEGER REFERENCES objects(id),
+    object_name TEXT NOT NULL,
+    user BigInt NOT NULL REFERENCES users(snowflake),
+    destination_name TEXT NOT NULL,
+    url TEXT NOT NULL,
+    deletion_url TEXT,
+    created_unix BigInt NOT NULL,
+    deleted_unix BigInt
+);
+
+CREATE INDEX uploads_object ON uploads (object_id);
@@ -0,0 +1,198 @@
+use poise::serenity_prelude::{
+    self as serenity, CreateActionRow, CreateButton, CreateEmbed, CreateSelectMenu,
+    CreateSelectMenuOption,
+};
+
+use crate::{
+    Data,
+    db::{self, Object},
+};
+
+const PAGE_SIZE: i64 = 10;
+
+#[derive(poise::ChoiceParameter, Clone, Copy, PartialEq)]
+pub enum ObjectSort {
+    #[name = "Expiry"]
+    Expiry,
+    #[name = "Size"]
+    Size,
+    #[name = "Name"]
+    Name,
+    #[name = "Created"]
+    Created,
+}
+
+impl ObjectSort {
+    const ALL: [ObjectSort; 4] = [
+        ObjectSort::Expiry,
+        ObjectSort::Size,
+        ObjectSort::Name,
+        ObjectSort::Created,
+    ];
+
+    fn id(&self) -> &'static str {
+        match self {
+            ObjectSort::Expiry => "expiry",
+            ObjectSort::Size => "size",
+            ObjectSort::Name => "name",
+            ObjectSort::Created => "created",
+        }
+    }
+
+    fn from_id(id: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|sort| sort.id() == id)
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            ObjectSort::Expiry => "Expiring soonest",
+            ObjectSort::Size => "Largest first",
+            ObjectSort::Name => "Name",
+            ObjectSort::Created => "Newest first",
+        }
+    }
+}
+
+// Browser state lives in the component custom ids, e.g. "Browse:<user>:<sort>:<page>", so any
+// page can be rendered again after a restart
+pub struct BrowserState {
+    pub user: i64,
+    pub sort: ObjectSort,
+    pub page: i64,
+}
+
+impl BrowserState {
+    pub fn parse(custom_id: &str) -> Option<Self> {
+        let mut parts = custom_id.strip_prefix("Browse:")?.split(':');
+        Some(Self {
+            user: parts.next()?.parse().ok()?,
+            sort: ObjectSort::from_id(parts.next()?)?,
+            page: parts.next()?.parse().ok()?,
+        })
+    }
+
+    fn custom_id(&self, page: i64) -> String {
+        format!("Browse:{}:{}:{}", self.user, self.sort.id(), page)
+    }
+}
+
+// The sort menu only knows the user, the chosen sort comes in as its value
+pub fn parse_sort_menu(custom_id: &str, value: &str) -> Option<BrowserState> {
+    Some(BrowserState {
+        user: custom_id.strip_prefix("BrowseSort:")?.parse().ok()?,
+        sort: ObjectSort::from_id(value)?,
+        page: 0,
+    })
+}
+
+fn object_line(object: &Object) -> String {
+    let expires = if object.expiry_unix == db::PINNED {
+        "pinned".to_owned()
+    } else {
+        format!("expires <t:{}:R>", object.expiry_unix)
+    };
+    format!(
+        "`[{}]` {} ({}, {})\n",
+        object.id,
+        object.name.chars().take(80).collect::<String>(),
+        humansize::format_size(object.size as u64, humansize::DECIMAL),
+        expires
+    )
+}
+
+pub async fn render(
+    data: &Data,
+    state: BrowserState,
+) -> anyhow::Result<(CreateEmbed, Vec<CreateActionRow>)> {
+    let uid = state.user;
+    let sort = state.sort;
+    let requested_page = state.page;
+    let (total, objects, page) = data
+        .db
+        .get()
+        .await?
+        .interact(move |x| {
+            use crate::schema::objects::dsl::*;
+            use diesel::prelude::*;
+            let now = db::unix_now();
+            let owned = || objects.filter(user.eq(uid)).filter(expiry_unix.gt(now));
+            let total = owned().count().get_result::<i64>(x)?;
+            let last_page = ((total - 1) / PAGE_SIZE).max(0);
+            let page = requested_page.clamp(0, last_page);
+            let query = owned().select(Object::as_select()).into_boxed();
+            let query = match sort {
+                ObjectSort::Expiry => query.order_by((expiry_unix.asc(), id.asc())),
+                ObjectSort::Size => query.order_by((size.desc(), id.asc())),
+                ObjectSort::Name => query.order_by((name.asc(), id.asc())),
+                ObjectSort::Created => query.order_by((created_unix.desc(), id.desc())),
+            };
+            let page_objects = query
+                .limit(PAGE_SIZE)
+                .offset(page * PAGE_SIZE)
+                .load(x)?;
+            Ok::<_, diesel::result::Error>((total, page_objects, page))
+        })
+        .await
+        .unwrap()?;
+    let pages = ((total + PAGE_SIZE - 1) / PAGE_SIZE).max(1);
+    let state = BrowserState { page, ..state };
+
+    let listing = if objects.is_empty() {
+        "You don't have any objects yet".to_owned()
+    } else {
+        objects.iter().map(object_line).collect()
+    };
+    let embed = CreateEmbed::new()
+        .title(format!("Your objects ({total})"))
+        .description(listing)
+        .color(serenity::Color::from_rgb(0, 0, 255))
+        .footer(serenity::CreateEmbedFooter::new(format!(
+            "Page {}/{} · {}",
+            page + 1,
+            pages,
+            sort.label()
+        )));
+
+    let mut components = vec![];
+    if !objects.is_empty() {
+        let options = objects
+            .iter()
+            .map(|object| {
+                let label: String = object.name.chars().take(100).collect();
+                CreateSelectMenuOption::new(label, object.id.to_string()).description(
+                    humansize::format_size(object.size as u64, humansize::DECIMAL),
+                )
+            })
+            .collect();
+        components.push(CreateActionRow::SelectMenu(
+            CreateSelectMenu::new("OpenObject", serenity::CreateSelectMenuKind::String { options })
+                .placeholder("Open object"),
+        ));
+    }
+    let sort_options = ObjectSort::ALL
+        .into_iter()
+        .map(|option| {
+            CreateSelectMenuOption::new(option.label(), option.id()).default_selection(option == sort)
+        })
+        .collect();
+    components.push(CreateActionRow::SelectMenu(
+        CreateSelectMenu::new(
+            format!("BrowseSort:{uid}"),
+            serenity::CreateSelectMenuKind::String {
+                options: sort_options,
+            },
+        )
+        .placeholder("Sort by"),
+    ));
+    components.push(CreateActionRow::Buttons(vec![
+        CreateButton::new(state.custom_id(page - 1))
+            .label("Previous")
+            .style(serenity::ButtonStyle::Secondary)
+            .disabled(page == 0),
+        CreateButton::new(state.custom_id(page + 1))
+            .label("Next")
+            .style(serenity::ButtonStyle::Secondary)
+            .disabled(page + 1 >= pages),
+    ]));
+    Ok((embed, components))
+}
@@ -0,0 +1,271 @@
+use std::sync::Arc;
+
+use chacha20poly1305::{
+    AeadCore, KeyInit, XChaCha20Poly1305, XNonce,
+    aead::{Aead, OsRng},
+};
+use diesel::prelude::*;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    db::{Destination, Upload},
+    sharex,
+};
+
+// Encrypted values look like enc:<key id>:<hex nonce and ciphertext>, anything else is plaintext
+const PREFIX: &str = "enc:";
+const NONCE_LENGTH: usize = 24;
+
+#[derive(Clone)]
+struct Key {
+    id: String,
+    cipher: XChaCha20Poly1305,
+}
+
+impl Key {
+    fn parse(hex_key: &str) -> anyhow::Result<Self> {
+        let bytes = hex::decode(hex_key.trim())?;
+        anyhow::ensure!(bytes.len() == 32, "keys have to be 32 bytes of hex");
+        // The id only tells keys apart, so a short hash is enough
+        let id = hex::encode(&Sha256::digest(&bytes)[..4]);
+        Ok(Self {
+            id,
+            cipher: XChaCha20Poly1305::new_from_slice(&bytes).unwrap(),
+        })
+    }
+}
+
+// Values are encrypted with CREDENTIALS_KEY, CREDENTIALS_OLD_KEYS can still decrypt until they're rotated out
+#[derive(Clone, Default)]
+pub struct Credentials {
+    current: Option<Arc<Key>>,
+    old: Arc<Vec<Key>>,
+}
+
+impl Credentials {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let current = match std::env::var("CREDENTIALS_KEY") {
+            Ok(key) => Some(Arc::new(
+                Key::parse(&key).map_err(|e| anyhow::anyhow!("CREDENTIALS_KEY: {}", e))?,
+            )),
+            Err(_) => None,
+        };
+        let old = std::env::var("CREDENTIALS_OLD_KEYS")
+            .unwrap_or_default()
+            .split(',')
+            .filter(|key| !key.trim().is_empty())
+            .map(|key| Key::parse(key).map_err(|e| anyhow::anyhow!("CREDENTIALS_OLD_KEYS: {}", e)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Self {
+            current,
+            old: Arc::new(old),
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.current.is_some()
+    }
+
+    // Without a key values are stored as-is
+    pub fn encrypt(&self, plaintext: &str) -> anyhow::Result<String> {
+        let Some(key) = &self.current else {
+            return Ok(plaintext.to_owned());
+        };
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = key
+            .cipher
+            .encrypt(&nonce, plaintext.as_bytes())
+            .map_err(|_| anyhow::anyhow!("Encrypting a credential failed"))?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+        Ok(format!("{}{}:{}", PREFIX, key.id, hex::encode(sealed)))
+    }
+
+    pub fn decrypt(&self, value: &str) -> anyhow::Result<String> {
+        let Some((key_id, sealed)) = value
+            .strip_prefix(PREFIX)
+            .and_then(|value| value.split_once(':'))
+        else {
+            return Ok(value.to_owned());
+        };
+        let key = self
+            .current
+            .iter()
+            .map(|key| key.as_ref())
+            .chain(self.old.iter())
+            .find(|key| key.id == key_id)
+            .ok_or_else(|| anyhow::anyhow!("A credential is encrypted with unknown key {}", key_id))?;
+        let sealed = hex::decode(sealed)?;
+        anyhow::ensure!(sealed.len() > NONCE_LENGTH, "An encrypted credential is truncated");
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LENGTH);
+        let plaintext = key
+            .cipher
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| anyhow::anyhow!("A credential failed to decrypt with key {}", key_id))?;
+        Ok(String::from_utf8(plaintext)?)
+    }
+
+    fn is_current(&self, value: &str) -> bool {
+        match &self.current {
+            Some(key) => value.starts_with(&format!("{}{}:", PREFIX, key.id)),
+            None => !value.starts_with(PREFIX),
+        }
+    }
+
+    // Decrypts with whichever key was used and encrypts again with the current one
+    fn reencrypt(&self, value: &str) -> anyhow::Result<String> {
+        if self.is_current(value) {
+            return Ok(value.to_owned());
+        }
+        self.encrypt(&self.decrypt(value)?)
+    }
+}
+
+#[derive(Default)]
+pub struct Rotation {
+    pub rotated: usize,
+    // Rows that couldn't be read, these still need the key they were encrypted with
+    pub skipped: Vec<String>,
+}
+
+// Re-encrypts every stored credential with the current key
+pub fn rotate(credentials: &Credentials, conn: &mut SqliteConnection) -> anyhow::Result<Rotation> {
+    use crate::schema::destinations::dsl::*;
+    conn.transaction(|x| {
+        let mut rotation = Rotation::default();
+        for destination in destinations.select(Destination::as_select()).load(x)? {
+            let resealed = serde_json::from_str(&destination.json)
+                .map_err(anyhow::Error::from)
+                .and_then(|mut config| {
+                    let changed =
+                        sharex::map_secrets(&mut config, |secret| credentials.reencrypt(secret))?;
+                    Ok(changed.then(|| config.to_string()))
+                });
+            match resealed {
+                Ok(Some(resealed)) => {
+                    diesel::update(destinations.find(destination.id))
+                        .set(json.eq(resealed))
+                        .execute(x)?;
+                    rotation.rotated += 1;
+                }
+                Ok(None) => {}
+                Err(e) => rotation.skipped.push(format!(
+                    "Destination {} ({}): {}",
+                    destination.id, destination.name, e
+                )),
+            }
+        }
+        rotate_uploads(credentials, &mut rotation, x)?;
+        Ok(rotation)
+    })
+}
+
+fn rotate_uploads(
+    credentials: &Credentials,
+    rotation: &mut Rotation,
+    conn: &mut SqliteConnection,
+) -> anyhow::Result<()> {
+    use crate::schema::uploads::dsl::*;
+    for upload in uploads
+        .filter(deletion_url.is_not_null())
+        .select(Upload::as_select())
+        .load(conn)?
+    {
+        let sealed = upload.deletion_url.unwrap_or_default();
+        match credentials.reencrypt(&sealed) {
+            Ok(resealed) if resealed != sealed => {
+                diesel::update(uploads.find(upload.id))
+                    .set(deletion_url.eq(resealed))
+                    .execute(conn)?;
+                rotation.rotated += 1;
+            }
+            Ok(_) => {}
+            Err(e) => rotation.skipped.push(format!("Upload {}: {}", upload.id, e)),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{self, NewDestination, NewUpload};
+
+    const OLD_KEY: &str = "0101010101010101010101010101010101010101010101010101010101010101";
+    const NEW_KEY: &str = "0202020202020202020202020202020202020202020202020202020202020202";
+    const UPLOADER: &str = r#"{"RequestURL":"https://example.com/upload?key=secret","Headers":{"Authorization":"Bearer token"}}"#;
+
+    fn credentials(current: &str, old: &[&str]) -> Credentials {
+        Credentials {
+            current: Some(Arc::new(Key::parse(current).unwrap())),
+            old: Arc::new(old.iter().map(|key| Key::parse(key).unwrap()).collect()),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let credentials = credentials(NEW_KEY, &[]);
+        let sealed = credentials.encrypt("secret").unwrap();
+        assert!(sealed.starts_with(PREFIX));
+        assert!(!sealed.contains("secret"));
+        assert_ne!(sealed, credentials.encrypt("secret").unwrap());
+        assert_eq!(credentials.decrypt(&sealed).unwrap(), "secret");
+        assert!(credentials.decrypt(&sealed[..sealed.len() - 2]).is_err());
+    }
+
+    #[test]
+    fn plaintext_passes_through() {
+        assert_eq!(credentials(NEW_KEY, &[]).decrypt("secret").unwrap(), "secret");
+        let disabled = Credentials::default();
+        assert_eq!(disabled.encrypt("secret").unwrap(), "secret");
+        assert_eq!(disabled.decrypt("secret").unwrap(), "secret");
+    }
+
+    #[test]
+    fn rotation_moves_everything_to_the_new_key() {
+        let mut conn = db::test_connection();
+        let old = credentials(OLD_KEY, &[]);
+        diesel::insert_into(crate::schema::destinations::table)
+            .values(NewDestination {
+                user: 1,
+                name: "example".to_owned(),
+                json: sharex::seal(UPLOADER, &old).unwrap(),
+                is_default: true,
+                created_unix: 0,
+            })
+            .execute(&mut conn)
+            .unwrap();
+        diesel::insert_into(crate::schema::uploads::table)
+            .values(NewUpload {
+                object_id: None,
+                object_name: "object".to_owned(),
+                user: 1,
+                destination_name: "example".to_owned(),
+                url: "https://example.com/file".to_owned(),
+                deletion_url: Some(old.encrypt("https://example.com/delete").unwrap()),
+                created_unix: 0,
+            })
+            .execute(&mut conn)
+            .unwrap();
+
+        let rotation = rotate(&credentials(NEW_KEY, &[OLD_KEY]), &mut conn).unwrap();
+        assert_eq!(rotation.rotated, 2);
+        assert!(rotation.skipped.is_empty());
+
+        let new = credentials(NEW_KEY, &[]);
+        let stored = crate::schema::destinations::table
+            .select(crate::schema::destinations::json)
+            .first::<String>(&mut conn)
+            .unwrap();
+        let uploader = sharex::CustomUploader::from_stored(&stored, &new).unwrap();
+        assert_eq!(uploader.request_url, "https://example.com/upload?key=secret");
+        assert_eq!(uploader.headers["Authorization"], "Bearer token");
+        assert!(sharex::CustomUploader::from_stored(&stored, &old).is_err());
+        let deletion_url = crate::schema::uploads::table
+            .select(crate::schema::uploads::deletion_url.assume_not_null())
+            .first::<String>(&mut conn)
+            .unwrap();
+        assert_eq!(new.decrypt(&deletion_url).unwrap(), "https://example.com/delete");
+        assert!(old.decrypt(&deletion_url).is_err());
+    }
+}
@@ -2,6 +2,7 @@ use std::time::{SystemTime, UNIX_EPOCH};
 
 use deadpool_diesel::{Manager, Pool};
 use diesel::prelude::*;
+use serde::Deserialize;
 
 use crate::schema;
 
@@ -15,6 +16,25 @@ pub struct Object {
     pub size: i64,
     pub expiry_unix: i64,
     pub user: i64,
+    pub blob_hash: Option<String>,
+    pub source_url: Option<String>,
+    pub extractor_id: Option<String>,
+    pub created_unix: i64,
+}
+
+impl Object {
+    // Object names usually come from titles and don't carry the file extension
+    pub fn file_name(&self) -> String {
+        let extension = std::path::Path::new(&self.path)
+            .extension()
+            .and_then(|extension| extension.to_str());
+        match extension {
+            Some(extension) if !self.name.ends_with(&format!(".{extension}")) => {
+                format!("{}.{}", self.name, extension)
+            }
+            _ => self.name.clone(),
+        }
+    }
 }
 
 #[derive(Insertable)]
@@ -27,20 +47,40 @@ pub struct NewObject {
     pub size: i64,
     pub expiry_unix: i64,
     pub user: i64,
+    pub blob_hash: Option<String>,
+    pub source_url: Option<String>,
+    pub extractor_id: Option<String>,
+    pub created_unix: i64,
 }
 
-impl NewObject {
-    pub fn new_with_extension(ext: &str) -> NewObject {
-        let tmpfile = tempfile::NamedTempFile::with_suffix(".".to_owned() + ext).unwrap();
-        let tmpfile = tmpfile.into_temp_path().keep().unwrap();
-        NewObject {
-            path: tmpfile.to_string_lossy().to_owned().to_string(),
-            name: "".to_owned(),
-            size: 0,
-            expiry_unix: 0,
-            user: 0,
-        }
-    }
+#[derive(Queryable, Selectable, Insertable, Deserialize, Clone, Default)]
+#[diesel(table_name = crate::schema::object_metadata)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct ObjectMetadata {
+    #[serde(skip)]
+    pub object_id: i32,
+    pub title: Option<String>,
+    pub uploader: Option<String>,
+    pub upload_date: Option<String>,
+    pub duration: Option<f64>,
+    pub description: Option<String>,
+    pub original_url: Option<String>,
+    #[serde(rename = "extractor_key")]
+    pub extractor: Option<String>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub vcodec: Option<String>,
+    pub acodec: Option<String>,
+}
+
+#[derive(Queryable, Selectable, Insertable, Clone)]
+#[diesel(table_name = crate::schema::blobs)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct Blob {
+    pub hash: String,
+    pub path: String,
+    pub size: i64,
+    pub refcount: i32,
 }
 
 #[derive(Queryable, Selectable, Insertable, Clone)]
@@ -49,6 +89,82 @@ impl NewObject {
 pub struct User {
     pub snowflake: i64,
     pub name_cached: Option<String>,
+    pub role: String,
+    pub bytes_used: i64,
+    // None means the default quota applies
+    pub bytes_allowed: Option<i64>,
+    pub object_count: i32,
+}
+
+// Expiry of pinned objects
+pub const PINNED: i64 = i64::MAX;
+pub const DEFAULT_ROLE: &str = "user";
+pub const ADMIN_ROLE: &str = "admin";
+
+#[derive(Queryable, Selectable, Insertable, Clone)]
+#[diesel(table_name = crate::schema::retention_limits)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct RetentionLimit {
+    pub role: String,
+    // None means objects can be kept (and pinned) forever
+    pub max_days: Option<i32>,
+}
+
+pub fn unix_now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+}
+
+pub fn default_quota() -> i64 {
+    std::env::var("DEFAULT_QUOTA_BYTES")
+        .ok()
+        .and_then(|quota| quota.parse().ok())
+        .unwrap_or(5_000_000_000)
+}
+
+// Adds an object's size to its owner's usage, failing if that would go over their quota
+pub fn charge_user(uid: i64, bytes: i64, conn: &mut SqliteConnection) -> anyhow::Result<()> {
+    use crate::schema::users::dsl::*;
+    let user = users
+        .find(uid)
+        .select(User::as_select())
+        .first(conn)
+        .optional()?;
+    if let Some(user) = &user {
+        anyhow::ensure!(
+            bytes <= user.bytes_remaining(),
+            "Storage quota exceeded: {} needed but only {} of {} left",
+            humansize::format_size(bytes as u64, humansize::DECIMAL),
+            humansize::format_size(user.bytes_remaining() as u64, humansize::DECIMAL),
+            humansize::format_size(user.quota() as u64, humansize::DECIMAL)
+        );
+    }
+    diesel::update(users.find(uid))
+        .set((
+            bytes_used.eq(bytes_used + bytes),
+            object_count.eq(object_count + 1),
+        ))
+        .execute(conn)?;
+    Ok(())
+}
+
+fn refund_user(uid: i64, bytes: i64, conn: &mut SqliteConnection) -> QueryResult<()> {
+    use crate::schema::users::dsl::*;
+    diesel::update(users.find(uid))
+        .set((
+            bytes_used.eq(bytes_used - bytes),
+            object_count.eq(object_count - 1),
+        ))
+        .execute(conn)?;
+    Ok(())
+}
+
+// Users listed in ADMIN_USER_IDS are admins regardless of their stored role
+pub fn admin_ids() -> Vec<i64> {
+    std::env::var("ADMIN_USER_IDS")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|id| id.trim().parse().ok())
+        .collect()
 }
 
 impl User {
@@ -77,6 +193,10 @@ impl User {
             let user = User {
                 snowflake: user_snowflake,
                 name_cached: name,
+                role: DEFAULT_ROLE.to_owned(),
+                bytes_used: 0,
+                bytes_allowed: None,
+                object_count: 0,
             };
             diesel::insert_into(crate::schema::users::table)
                 .values(&[user.clone()])
@@ -92,15 +212,357 @@ impl User {
             .first::<User>(db)?;
         Ok(user)
     }
+
+    pub fn quota(&self) -> i64 {
+        self.bytes_allowed.unwrap_or_else(default_quota)
+    }
+
+    pub fn bytes_remaining(&self) -> i64 {
+        (self.quota() - self.bytes_used).max(0)
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.role == ADMIN_ROLE || admin_ids().contains(&self.snowflake)
+    }
+
+    pub fn effective_role(&self) -> &str {
+        if self.is_admin() { ADMIN_ROLE } else { &self.role }
+    }
+
+    // Longest time an object may be kept for, in seconds. None if there is no limit
+    pub fn max_retention(&self, conn: &mut SqliteConnection) -> QueryResult<Option<i64>> {
+        use crate::schema::retention_limits::dsl::*;
+        let limit = retention_limits
+            .filter(role.eq_any([self.effective_role(), DEFAULT_ROLE]))
+            .select(RetentionLimit::as_select())
+            .load(conn)?;
+        // Roles without their own limit fall back to the default role's
+        let limit = limit
+            .iter()
+            .find(|limit| limit.role == self.effective_role())
+            .or(limit.first());
+        Ok(limit
+            .and_then(|limit| limit.max_days)
+            .map(|days| days as i64 * 60 * 60 * 24))
+    }
 }
 
-#[derive(Queryable, Selectable, Insertable, Clone)]
-#[diesel(table_name = crate::schema::sharex_config)]
+// A ShareX custom uploader config saved by a user under a name
+#[derive(Queryable, Selectable, Clone)]
+#[diesel(table_name = crate::schema::destinations)]
 #[diesel(check_for_backend(diesel::sqlite::Sqlite))]
-#[diesel(belongs_to(User))]
-pub struct SharexConfig {
-    pub user_id: i64,
+pub struct Destination {
+    pub id: i32,
+    pub user: i64,
+    pub name: String,
     pub json: String,
+    pub is_default: bool,
+    pub created_unix: i64,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::destinations)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct NewDestination {
+    pub user: i64,
+    pub name: String,
+    pub json: String,
+    pub is_default: bool,
+    pub created_unix: i64,
+}
+
+impl Destination {
+    pub fn list(uid: i64, conn: &mut SqliteConnection) -> QueryResult<Vec<Self>> {
+        use crate::schema::destinations::dsl::*;
+        destinations
+            .filter(user.eq(uid))
+            .order_by((is_default.desc(), name.asc()))
+            .select(Destination::as_select())
+            .load(conn)
+    }
+
+    // The given destination, or the user's default one if there's no id
+    pub fn find(
+        uid: i64,
+        destination_id: Option<i32>,
+        conn: &mut SqliteConnection,
+    ) -> QueryResult<Option<Self>> {
+        use crate::schema::destinations::dsl::*;
+        let query = destinations.filter(user.eq(uid)).into_boxed();
+        let query = match destination_id {
+            Some(destination_id) => query.filter(id.eq(destination_id)),
+            None => query.filter(is_default.eq(true)),
+        };
+        query.select(Destination::as_select()).first(conn).optional()
+    }
+
+    pub fn set_default(&self, conn: &mut SqliteConnection) -> QueryResult<()> {
+        use crate::schema::destinations::dsl::*;
+        conn.transaction(|conn| {
+            diesel::update(destinations.filter(user.eq(self.user)))
+                .set(is_default.eq(id.eq(self.id)))
+                .execute(conn)?;
+            Ok(())
+        })
+    }
+
+    // If this was the default, the oldest remaining destination takes over
+    pub fn delete(&self, conn: &mut SqliteConnection) -> QueryResult<Option<Destination>> {
+        use crate::schema::destinations::dsl::*;
+        conn.transaction(|conn| {
+            diesel::delete(destinations.find(self.id)).execute(conn)?;
+            if !self.is_default {
+                return Ok(None);
+            }
+            let next = destinations
+                .filter(user.eq(self.user))
+                .order_by(created_unix.asc())
+                .select(Destination::as_select())
+                .first(conn)
+                .optional()?;
+            if let Some(next) = &next {
+                next.set_default(conn)?;
+            }
+            Ok(next)
+        })
+    }
+}
+
+// A copy of an object uploaded to one of its user's destinations
+#[derive(Queryable, Selectable, Clone)]
+#[diesel(table_name = crate::schema::uploads)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct Upload {
+    pub id: i32,
+    pub object_id: Option<i32>,
+    pub object_name: String,
+    pub user: i64,
+    pub destination_name: String,
+    pub url: String,
+    pub deletion_url: Option<String>,
+    pub created_unix: i64,
+    pub deleted_unix: Option<i64>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::uploads)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct NewUpload {
+    pub object_id: Option<i32>,
+    pub object_name: String,
+    pub user: i64,
+    pub destination_name: String,
+    pub url: String,
+    pub deletion_url: Option<String>,
+    pub created_unix: i64,
+}
+
+impl Upload {
+    // Upload URLs are only shown to whoever uploaded them. Newest first
+    pub fn for_object(oid: i32, uid: i64, conn: &mut SqliteConnection) -> QueryResult<Vec<Self>> {
+        use crate::schema::uploads::dsl::*;
+        uploads
+            .filter(object_id.eq(oid))
+            .filter(user.eq(uid))
+            .order_by(created_unix.desc())
+            .select(Upload::as_select())
+            .load(conn)
+    }
+
+    // Includes uploads of objects that have since been deleted
+    pub fn recent(uid: i64, limit: i64, conn: &mut SqliteConnection) -> QueryResult<Vec<Self>> {
+        use crate::schema::uploads::dsl::*;
+        uploads
+            .filter(user.eq(uid))
+            .order_by(created_unix.desc())
+            .limit(limit)
+            .select(Upload::as_select())
+            .load(conn)
+    }
+
+    pub fn can_delete(&self, uid: i64) -> bool {
+        self.user == uid && self.deletion_url.is_some() && self.deleted_unix.is_none()
+    }
+}
+
+#[derive(Queryable, Selectable, Clone)]
+#[diesel(table_name = crate::schema::collections)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct Collection {
+    pub id: i32,
+    pub user: i64,
+    pub name: String,
+    pub source_url: Option<String>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::collections)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct NewCollection {
+    pub user: i64,
+    pub name: String,
+    pub source_url: Option<String>,
+}
+
+impl NewCollection {
+    pub fn create_with_objects(
+        self,
+        object_ids: Vec<i32>,
+        conn: &mut SqliteConnection,
+    ) -> anyhow::Result<Collection> {
+        use crate::schema::collection_objects::dsl::*;
+        conn.transaction(|conn| {
+            let collection = diesel::insert_into(crate::schema::collections::table)
+                .values(&self)
+                .returning(Collection::as_returning())
+                .get_result(conn)?;
+            let rows = object_ids
+                .iter()
+                .map(|oid| (collection_id.eq(collection.id), object_id.eq(*oid)))
+                .collect::<Vec<_>>();
+            diesel::insert_into(collection_objects)
+                .values(&rows)
+                .execute(conn)?;
+            Ok(collection)
+        })
+    }
+}
+
+impl Collection {
+    pub fn find_owned(
+        collection_id: i32,
+        uid: i64,
+        conn: &mut SqliteConnection,
+    ) -> QueryResult<Option<Self>> {
+        use crate::schema::collections::dsl::*;
+        collections
+            .find(collection_id)
+            .filter(user.eq(uid))
+            .select(Collection::as_select())
+            .first(conn)
+            .optional()
+    }
+
+    pub fn objects(&self, conn: &mut SqliteConnection) -> QueryResult<Vec<Object>> {
+        use crate::schema::{collection_objects, objects};
+        objects::table
+            .inner_join(collection_objects::table)
+            .filter(collection_objects::collection_id.eq(self.id))
+            .order_by(objects::id.asc())
+            .select(Object::as_select())
+            .load(conn)
+    }
+
+    // Returns how many of the objects weren't in the collection yet
+    pub fn add_objects(&self, object_ids: &[i32], conn: &mut SqliteConnection) -> QueryResult<usize> {
+        use crate::schema::collection_objects::dsl::*;
+        let rows = object_ids
+            .iter()
+            .map(|oid| (collection_id.eq(self.id), object_id.eq(*oid)))
+            .collect::<Vec<_>>();
+        diesel::insert_or_ignore_into(collection_objects)
+            .values(&rows)
+            .execute(conn)
+    }
+
+    pub fn remove_object(&self, oid: i32, conn: &mut SqliteConnection) -> QueryResult<usize> {
+        use crate::schema::collection_objects::dsl::*;
+        diesel::delete(collection_objects.find((self.id, oid))).execute(conn)
+    }
+
+    // Only removes the collection itself, its objects are left alone
+    pub fn delete(&self, conn: &mut SqliteConnection) -> QueryResult<()> {
+        conn.transaction(|conn| {
+            diesel::delete(
+                schema::collection_objects::table
+                    .filter(schema::collection_objects::collection_id.eq(self.id)),
+            )
+            .execute(conn)?;
+            diesel::delete(
+                schema::collection_shares::table
+                    .filter(schema::collection_shares::collection_id.eq(self.id)),
+            )
+            .execute(conn)?;
+            diesel::delete(schema::collections::table.find(self.id)).execute(conn)?;
+            Ok(())
+        })
+    }
+}
+
+#[derive(Queryable, Selectable, Insertable, Clone)]
+#[diesel(table_name = crate::schema::object_tags)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct ObjectTag {
+    pub object_id: i32,
+    pub tag: String,
+}
+
+pub const MAX_TAG_LENGTH: usize = 32;
+
+// Tags are stored lowercase so they match regardless of how they were typed
+pub fn normalize_tag(input: &str) -> Option<String> {
+    let normalized = input
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join("-")
+        .to_lowercase();
+    (!normalized.is_empty() && normalized.chars().count() <= MAX_TAG_LENGTH).then_some(normalized)
+}
+
+pub fn object_tags(oid: i32, conn: &mut SqliteConnection) -> QueryResult<Vec<String>> {
+    use crate::schema::object_tags::dsl::*;
+    object_tags
+        .filter(object_id.eq(oid))
+        .order_by(tag.asc())
+        .select(tag)
+        .load(conn)
+}
+
+#[derive(Queryable, Selectable, Insertable, Clone)]
+#[diesel(table_name = crate::schema::object_shares)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct ObjectShare {
+    pub object_id: i32,
+    pub user: i64,
+    pub access: String,
+    pub created_unix: i64,
+}
+
+#[derive(Queryable, Selectable, Insertable, Clone)]
+#[diesel(table_name = crate::schema::collection_shares)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct CollectionShare {
+    pub collection_id: i32,
+    pub user: i64,
+    pub access: String,
+    pub created_unix: i64,
+}
+
+#[derive(Queryable, Selectable, Clone)]
+#[diesel(table_name = crate::schema::jobs)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct Job {
+    pub id: i32,
+    pub user: i64,
+    pub kind: String,
+    pub spec: String,
+    pub status: String,
+    pub output: Option<String>,
+    pub error: Option<String>,
+    pub created_unix: i64,
+    pub updated_unix: i64,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::jobs)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct NewJob {
+    pub user: i64,
+    pub kind: String,
+    pub spec: String,
+    pub status: String,
+    pub created_unix: i64,
+    pub updated_unix: i64,
 }
 
 pub type DatabasePool = Pool<Manager<SqliteConnection>>;
@@ -112,10 +574,246 @@ pub async fn create_database_pool() -> DatabasePool {
     pool
 }
 
+// Applies every migration's up.sql in order, for tests running against an in-memory database
+#[cfg(test)]
+fn run_migrations(conn: &mut SqliteConnection) {
+    use diesel::connection::SimpleConnection;
+    let mut migrations = std::fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/migrations"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_dir())
+        .collect::<Vec<_>>();
+    migrations.sort();
+    for migration in migrations {
+        let sql = std::fs::read_to_string(migration.join("up.sql")).unwrap();
+        conn.batch_execute(&sql).unwrap();
+    }
+}
+
+#[cfg(test)]
+pub fn test_connection() -> SqliteConnection {
+    let mut conn = SqliteConnection::establish(":memory:").unwrap();
+    run_migrations(&mut conn);
+    conn
+}
+
+// Every in-memory connection is its own database, so the pool keeps a single one around
+#[cfg(test)]
+pub async fn test_pool() -> DatabasePool {
+    let manager = deadpool_diesel::Manager::new(":memory:", deadpool::Runtime::Tokio1);
+    let pool: DatabasePool = Pool::builder(manager).max_size(1).build().unwrap();
+    pool.get().await.unwrap().interact(run_migrations).await.unwrap();
+    pool
+}
+
+
+// Deletes the object and drops its reference to the blob. Returns the file that should be removed
+// from disk, if nothing references it anymore
+pub fn delete_object(
+    object_id: i32,
+    conn: &mut SqliteConnection,
+) -> anyhow::Result<Option<String>> {
+    conn.transaction(|conn| {
+        diesel::delete(
+            schema::collection_objects::table
+                .filter(schema::collection_objects::object_id.eq(object_id)),
+        )
+        .execute(conn)?;
+        diesel::delete(schema::object_metadata::table.find(object_id)).execute(conn)?;
+        diesel::delete(
+            schema::object_tags::table.filter(schema::object_tags::object_id.eq(object_id)),
+        )
+        .execute(conn)?;
+        diesel::delete(
+            schema::object_shares::table.filter(schema::object_shares::object_id.eq(object_id)),
+        )
+        .execute(conn)?;
+        // Upload records stay so remote copies can still be deleted
+        diesel::update(schema::uploads::table.filter(schema::uploads::object_id.eq(object_id)))
+            .set(schema::uploads::object_id.eq(None::<i32>))
+            .execute(conn)?;
+        let object = diesel::delete(schema::objects::table.find(object_id))
+            .returning(Object::as_returning())
+            .get_result(conn)
+            .optional()?;
+        let Some(object) = object else {
+            return Ok(None);
+        };
+        refund_user(object.user, object.size, conn)?;
+        match object.blob_hash {
+            Some(blob_hash) => Ok(release_blob(&blob_hash, conn)?),
+            // Objects stored before deduplication own their file
+            None => Ok(Some(object.path)),
+        }
+    })
+}
+
+pub fn release_blob(blob_hash: &str, conn: &mut SqliteConnection) -> QueryResult<Option<String>> {
+    use crate::schema::blobs::dsl::*;
+    let blob = diesel::update(blobs.find(blob_hash))
+        .set(refcount.eq(refcount - 1))
+        .returning(Blob::as_returning())
+        .get_result(conn)
+        .optional()?;
+    match blob {
+        Some(blob) if blob.refcount <= 0 => {
+            diesel::delete(blobs.find(blob_hash)).execute(conn)?;
+            Ok(Some(blob.path))
+        }
+        _ => Ok(None),
+    }
+}
 
-pub fn delete_expired_files(conn: &mut SqliteConnection) -> anyhow::Result<()> {
-    let unix_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+// Looks for a live object archived from the same source. The user's own copy is kept for at least
+// the requested time, otherwise they get a new object sharing the other copy's blob. Sources that
+// produced several files (multi-part posts) aren't reused since only one of them would be returned
+pub fn reuse_cached_object(
+    uid: i64,
+    canonical_url: String,
+    extractor_key: Option<String>,
+    new_expiry_unix: i64,
+    conn: &mut SqliteConnection,
+) -> anyhow::Result<Option<Object>> {
+    let unix_time = unix_now();
     use crate::schema::objects::dsl::*;
-    diesel::delete(objects.filter(expiry_unix.lt(unix_time))).execute(conn)?;
-    Ok(())
-}
\ No newline at end of file
+    conn.transaction(|conn| {
+        let matching = objects
+            .filter(expiry_unix.gt(unix_time))
+            .filter(source_url.eq(&canonical_url).or(extractor_id.eq(&extractor_key)));
+        let files = matching.select(path).distinct().limit(2).load::<String>(conn)?;
+        if files.len() > 1 {
+            return Ok(None);
+        }
+        let own = matching
+            .filter(user.eq(uid))
+            .order_by(id.desc())
+            .select(Object::as_select())
+            .first(conn)
+            .optional()?;
+        if let Some(mut own) = own {
+            if new_expiry_unix > own.expiry_unix {
+                diesel::update(objects.find(own.id))
+                    .set(expiry_unix.eq(new_expiry_unix))
+                    .execute(conn)?;
+                own.expiry_unix = new_expiry_unix;
+            }
+            return Ok(Some(own));
+        }
+        let other = matching
+            .filter(blob_hash.is_not_null())
+            .order_by(id.desc())
+            .select(Object::as_select())
+            .first(conn)
+            .optional()?;
+        let Some(other) = other else {
+            return Ok(None);
+        };
+        charge_user(uid, other.size, conn)?;
+        {
+            use crate::schema::blobs::dsl::*;
+            diesel::update(blobs.find(other.blob_hash.as_ref().unwrap()))
+                .set(refcount.eq(refcount + 1))
+                .execute(conn)?;
+        }
+        let object = NewObject {
+            path: other.path,
+            name: other.name,
+            size: other.size,
+            expiry_unix: new_expiry_unix,
+            user: uid,
+            blob_hash: other.blob_hash,
+            source_url: other.source_url,
+            extractor_id: other.extractor_id,
+            created_unix: unix_time,
+        };
+        let object = diesel::insert_into(objects)
+            .values(&object)
+            .returning(Object::as_returning())
+            .get_result(conn)?;
+        let metadata = schema::object_metadata::table
+            .find(other.id)
+            .select(ObjectMetadata::as_select())
+            .first(conn)
+            .optional()?;
+        if let Some(metadata) = metadata {
+            diesel::insert_into(schema::object_metadata::table)
+                .values(ObjectMetadata {
+                    object_id: object.id,
+                    ..metadata
+                })
+                .execute(conn)?;
+        }
+        Ok(Some(object))
+    })
+}
+
+// Objects that outlived the retention limit of their owner's role, which can be lower than
+// their expiry if the limit or the role changed after they were stored
+fn over_retention_limit(unix_time: i64, conn: &mut SqliteConnection) -> QueryResult<Vec<i32>> {
+    use crate::schema::objects::dsl::*;
+    let limits = schema::retention_limits::table
+        .select(RetentionLimit::as_select())
+        .load(conn)?;
+    let admins = admin_ids();
+    let limited_roles = limits
+        .iter()
+        .map(|limit| limit.role.clone())
+        .filter(|limit_role| limit_role != DEFAULT_ROLE)
+        .collect::<Vec<_>>();
+    let admin_limited = limited_roles.iter().any(|limit_role| limit_role == ADMIN_ROLE);
+    let mut over = vec![];
+    for limit in limits {
+        let Some(max_days) = limit.max_days else {
+            continue;
+        };
+        let cutoff = unix_time - max_days as i64 * 60 * 60 * 24;
+        let candidates = objects.filter(created_unix.lt(cutoff)).into_boxed();
+        // Same rules as User::max_retention, users in ADMIN_USER_IDS have the admin role
+        let candidates = if limit.role == DEFAULT_ROLE {
+            // Users without a row or with a role that has no limit of its own get the default one
+            let other_roles = schema::users::table
+                .filter(schema::users::role.eq_any(&limited_roles))
+                .select(schema::users::snowflake);
+            if admin_limited {
+                candidates
+                    .filter(user.ne_all(other_roles))
+                    .filter(user.ne_all(&admins))
+            } else {
+                candidates.filter(user.ne_all(other_roles).or(user.eq_any(&admins)))
+            }
+        } else {
+            let role_users = schema::users::table
+                .filter(schema::users::role.eq(&limit.role))
+                .select(schema::users::snowflake);
+            if limit.role == ADMIN_ROLE {
+                candidates.filter(user.eq_any(role_users).or(user.eq_any(&admins)))
+            } else {
+                candidates
+                    .filter(user.eq_any(role_users))
+                    .filter(user.ne_all(&admins))
+            }
+        };
+        over.extend(candidates.select(id).load::<i32>(conn)?);
+    }
+    Ok(over)
+}
+
+// Returns how many objects expired and the files that were released by them
+pub fn delete_expired_files(conn: &mut SqliteConnection) -> anyhow::Result<(usize, Vec<String>)> {
+    let unix_time = unix_now();
+    use crate::schema::objects::dsl::*;
+    conn.transaction(|conn| {
+        let mut expired = objects
+            .filter(expiry_unix.lt(unix_time))
+            .select(id)
+            .load::<i32>(conn)?;
+        expired.extend(over_retention_limit(unix_time, conn)?);
+        expired.sort();
+        expired.dedup();
+        let mut released = vec![];
+        for object_id in &expired {
+            released.extend(delete_object(*object_id, conn)?);
+        }
+        Ok((expired.len(), released))
+    })
+}
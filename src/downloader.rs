@@ -1,7 +1,75 @@
+use std::path::Path;
+
 use poise::serenity_prelude::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::{db::ObjectMetadata, progress::ProgressReporter};
+
+pub struct SourceMetadata {
+    pub url: String,
+    pub extractor: Option<String>,
+    pub id: Option<String>,
+}
 
+pub struct DownloadedItem {
+    pub name: String,
+    pub file: tempfile::TempPath,
+    pub mime_type: String,
+    pub source: SourceMetadata,
+    pub metadata: Option<ObjectMetadata>,
+}
+
+impl DownloadedItem {
+    pub fn new(name: String, file: tempfile::TempPath, source: SourceMetadata) -> Self {
+        let mime_type = mime_guess::from_path(&file)
+            .first_or_octet_stream()
+            .to_string();
+        Self {
+            name,
+            file,
+            mime_type,
+            source,
+            metadata: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DownloadFailure {
+    pub entry: String,
+    pub reason: String,
+}
+
+#[derive(Default)]
+pub struct DownloadOutput {
+    pub title: Option<String>,
+    pub items: Vec<DownloadedItem>,
+    pub failures: Vec<DownloadFailure>,
+}
+
+// Moves a file out of a downloader's working directory into its own temp file,
+// so the directory can be dropped without taking the download with it
+pub fn adopt_file(path: &Path) -> std::io::Result<tempfile::TempPath> {
+    let ext = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_string())
+        .unwrap_or("bin".to_owned());
+    let file = crate::storage::staging_file(&ext)?;
+    std::fs::rename(path, &file)?;
+    // Downloaders copy the remote Last-Modified time, which would make the GC treat the file as
+    // an old orphan before it's stored
+    std::fs::File::options()
+        .write(true)
+        .open(&file)?
+        .set_modified(std::time::SystemTime::now())?;
+    Ok(file)
+}
 
 #[async_trait]
 pub trait Downloader {
-    async fn download(&self, url: String) -> Result<(String, tempfile::NamedTempFile), anyhow::Error>;
+    async fn download(
+        &self,
+        url: String,
+        progress: &ProgressReporter,
+    ) -> Result<DownloadOutput, anyhow::Error>;
 }
@@ -0,0 +1,172 @@
+use std::sync::Arc;
+
+use axum::{
+    Router,
+    body::Body,
+    extract::{Path, Request, State},
+    http::{HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use hmac::{Hmac, Mac};
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
+use sha2::Sha256;
+use tokio::net::TcpListener;
+use tower_http::services::ServeFile;
+use tracing::{error, info};
+
+use crate::db::{DatabasePool, Object, unix_now};
+
+// Direct links are only handed out if both PUBLIC_URL and LINK_SECRET are set
+#[derive(Clone)]
+pub struct Links {
+    base_url: String,
+    secret: Arc<Vec<u8>>,
+    ttl: i64,
+}
+
+impl Links {
+    pub fn from_env() -> Option<Self> {
+        let base_url = std::env::var("PUBLIC_URL").ok()?;
+        let secret = std::env::var("LINK_SECRET").ok()?;
+        let ttl = std::env::var("LINK_TTL_SECONDS")
+            .ok()
+            .and_then(|ttl| ttl.parse().ok())
+            .unwrap_or(60 * 60 * 24);
+        Some(Self {
+            base_url: base_url.trim_end_matches('/').to_owned(),
+            secret: Arc::new(secret.into_bytes()),
+            ttl,
+        })
+    }
+
+    // Object ids get reused after deletion, the owner and creation time pin the link to this object
+    fn mac(&self, object: &Object, expires: i64) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).unwrap();
+        mac.update(
+            format!(
+                "{}:{}:{}:{}",
+                object.id, object.user, object.created_unix, expires
+            )
+            .as_bytes(),
+        );
+        mac
+    }
+
+    // Returns the link and when it stops working, which is never after the object expires
+    pub fn sign(&self, object: &Object) -> (String, i64) {
+        let expires = (unix_now() + self.ttl).min(object.expiry_unix);
+        let signature = hex::encode(self.mac(object, expires).finalize().into_bytes());
+        let name = utf8_percent_encode(&object.file_name(), NON_ALPHANUMERIC).to_string();
+        let url = format!(
+            "{}/files/{}/{}/{}/{}",
+            self.base_url, object.id, expires, signature, name
+        );
+        (url, expires)
+    }
+
+    fn verify(&self, object: &Object, expires: i64, signature: &str) -> bool {
+        let Ok(signature) = hex::decode(signature) else {
+            return false;
+        };
+        self.mac(object, expires).verify_slice(&signature).is_ok()
+    }
+}
+
+fn content_disposition(object: &Object) -> HeaderValue {
+    let name = object.file_name();
+    let mime = mime_guess::from_path(&object.path).first_or_octet_stream();
+    // Media is shown in the browser so videos can be played and seeked, anything else downloads
+    let disposition = match mime.type_() {
+        mime_guess::mime::VIDEO | mime_guess::mime::AUDIO | mime_guess::mime::IMAGE => "inline",
+        _ => "attachment",
+    };
+    let fallback = name
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' => '_',
+            c if c.is_ascii_graphic() || c == ' ' => c,
+            _ => '_',
+        })
+        .collect::<String>();
+    let encoded = utf8_percent_encode(&name, NON_ALPHANUMERIC);
+    HeaderValue::from_str(&format!(
+        "{disposition}; filename=\"{fallback}\"; filename*=UTF-8''{encoded}"
+    ))
+    .unwrap_or(HeaderValue::from_static("attachment"))
+}
+
+#[derive(Clone)]
+struct ServerState {
+    db: DatabasePool,
+    links: Links,
+}
+
+async fn serve_object(
+    State(state): State<ServerState>,
+    Path((object_id, expires, signature, _name)): Path<(i32, i64, String, String)>,
+    request: Request,
+) -> Result<Response, StatusCode> {
+    let object = state
+        .db
+        .get()
+        .await
+        .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?
+        .interact(move |x| {
+            use crate::schema::objects::dsl::*;
+            use diesel::prelude::*;
+            objects
+                .find(object_id)
+                .filter(expiry_unix.gt(unix_now()))
+                .select(Object::as_select())
+                .first(x)
+                .optional()
+        })
+        .await
+        .unwrap()
+        .map_err(|e| {
+            error!("Loading object {} for a direct link failed: {}", object_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    if !state.links.verify(&object, expires, &signature) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    if expires < unix_now() {
+        return Err(StatusCode::GONE);
+    }
+    // ServeFile handles Range and conditional requests and sets Content-Type from the extension
+    let mut response = ServeFile::new(&object.path)
+        .try_call(request)
+        .await
+        .map_err(|e| {
+            error!("Serving {} failed: {}", object.path, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .map(Body::new);
+    if response.status().is_success() {
+        response
+            .headers_mut()
+            .insert(header::CONTENT_DISPOSITION, content_disposition(&object));
+    }
+    Ok(response.into_response())
+}
+
+// Bound separately so direct links are only offered once the server is actually listening
+pub async fn bind() -> anyhow::Result<TcpListener> {
+    let address = std::env::var("HTTP_BIND").unwrap_or_else(|_| "0.0.0.0:8080".to_owned());
+    let listener = TcpListener::bind(&address).await?;
+    info!("Serving direct links on {}", address);
+    Ok(listener)
+}
+
+pub async fn serve(listener: TcpListener, db: DatabasePool, links: Links) -> anyhow::Result<()> {
+    let app = Router::new()
+        .route(
+            "/files/{object_id}/{expires}/{signature}/{name}",
+            get(serve_object),
+        )
+        .with_state(ServerState { db, links });
+    axum::serve(listener, app).await?;
+    Ok(())
+}
@@ -0,0 +1,118 @@
+use poise::serenity_prelude::async_trait;
+use tokio::io::AsyncBufReadExt;
+
+use crate::{
+    downloader::{DownloadOutput, DownloadedItem, Downloader, SourceMetadata, adopt_file},
+    progress::{Progress, ProgressReporter},
+};
+
+const TITLE_START: &str = "GALLERYTITLE((![[";
+const TITLE_END: &str = "]]!))";
+
+pub struct GalleryDownloader {
+    pub max_filesize: Option<u64>,
+}
+
+#[async_trait]
+impl Downloader for GalleryDownloader {
+    async fn download(
+        &self,
+        url: String,
+        progress: &ProgressReporter,
+    ) -> Result<DownloadOutput, anyhow::Error> {
+        let tempdir = crate::storage::staging_workdir()?;
+        let max_files: u64 = std::env::var("GALLERYDL_MAX_FILES")
+            .ok()
+            .and_then(|max| max.parse().ok())
+            .unwrap_or(100);
+        let mut command = tokio::process::Command::new("gallery-dl");
+        command
+            .arg("-D")
+            .arg(tempdir.path())
+            .arg("--range")
+            .arg(format!("1-{}", max_files))
+            .arg("--print")
+            .arg("GALLERYINFO {category}")
+            .arg("--print")
+            .arg(format!("{TITLE_START}{{title|content|description|''}}{TITLE_END}"));
+        if let Some(max_filesize) = self.max_filesize {
+            command.arg("--filesize-max").arg(max_filesize.to_string());
+        }
+        if std::env::var("GALLERYDL_COOKIES_FILE").is_ok() {
+            command.arg("--cookies").arg(std::env::var("GALLERYDL_COOKIES_FILE").unwrap());
+        };
+        command.arg(&url);
+        command.stdout(std::process::Stdio::piped());
+        command.kill_on_drop(true);
+        let mut child = command.spawn()?;
+
+        let stdout = child.stdout.take().unwrap();
+        let bufreader = tokio::io::BufReader::new(stdout);
+        let mut lines = bufreader.lines();
+        let mut post_title = None;
+        let mut category = None;
+        // Post text (tweets etc.) can span several lines, so keep reading until the end marker
+        let mut pending: Option<String> = None;
+        let mut downloaded = 0;
+        let dir_prefix = tempdir.path().to_string_lossy().to_string();
+        while let Ok(Some(line)) = lines.next_line().await {
+            tracing::info!("{}", line);
+            if let (None, Some(info)) = (&pending, line.strip_prefix("GALLERYINFO ")) {
+                category.get_or_insert(info.trim().to_owned());
+                continue;
+            }
+            // Downloaded file paths are printed as-is, skipped ones are prefixed with "# "
+            if pending.is_none() && (line.starts_with(&dir_prefix) || line.starts_with("# ")) {
+                downloaded += 1;
+                progress.update(Progress::stage(format!("Downloaded {downloaded} files")));
+                continue;
+            }
+            let text = match pending.take() {
+                Some(text) => text + "\n" + &line,
+                None if line.starts_with(TITLE_START) => line[TITLE_START.len()..].to_owned(),
+                None => continue,
+            };
+            match text.find(TITLE_END) {
+                Some(end) => {
+                    let title = text[..end].trim();
+                    if post_title.is_none() && !title.is_empty() && title != "None" {
+                        post_title = Some(title.to_owned());
+                    }
+                }
+                None => pending = Some(text),
+            }
+        }
+        let status = child.wait().await?;
+        anyhow::ensure!(status.success(), "gallery-dl failed");
+
+        let mut paths = std::fs::read_dir(tempdir.path())?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && path.extension().is_none_or(|ext| ext != "part"))
+            .collect::<Vec<_>>();
+        paths.sort();
+        anyhow::ensure!(!paths.is_empty(), "gallery-dl did not produce any files");
+
+        let title = post_title.unwrap_or(url.clone());
+        let count = paths.len();
+        let mut items = vec![];
+        for (i, path) in paths.into_iter().enumerate() {
+            let name = if count > 1 {
+                format!("{} ({}/{})", title, i + 1, count)
+            } else {
+                title.clone()
+            };
+            let source = SourceMetadata {
+                url: url.clone(),
+                extractor: category.clone(),
+                id: path.file_stem().map(|stem| stem.to_string_lossy().to_string()),
+            };
+            items.push(DownloadedItem::new(name, adopt_file(&path)?, source));
+        }
+        Ok(DownloadOutput {
+            title: Some(title),
+            items,
+            failures: vec![],
+        })
+    }
+}
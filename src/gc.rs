@@ -0,0 +1,193 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use diesel::prelude::*;
+use tracing::warn;
+
+use crate::{
+    db::{self, Blob, Object},
+    storage,
+};
+
+// Files younger than this may have been moved into storage by a store that hasn't committed yet
+const ORPHAN_GRACE: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Default)]
+pub struct GcReport {
+    pub expired_objects: usize,
+    pub expired_bytes: u64,
+    pub orphaned_files: Vec<PathBuf>,
+    pub orphaned_bytes: u64,
+    pub missing_files: Vec<i32>,
+    pub fixed_refcounts: usize,
+    pub errors: Vec<String>,
+}
+
+impl Display for GcReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "removed {} expired objects ({}), {} orphaned files ({}), {} objects with missing files",
+            self.expired_objects,
+            humansize::format_size(self.expired_bytes, humansize::DECIMAL),
+            self.orphaned_files.len(),
+            humansize::format_size(self.orphaned_bytes, humansize::DECIMAL),
+            self.missing_files.len(),
+        )?;
+        if !self.missing_files.is_empty() {
+            write!(f, " {:?}", self.missing_files)?;
+        }
+        if self.fixed_refcounts > 0 {
+            write!(f, ", fixed {} blob refcounts", self.fixed_refcounts)?;
+        }
+        if !self.errors.is_empty() {
+            write!(f, ", {} errors", self.errors.len())?;
+        }
+        Ok(())
+    }
+}
+
+fn remove_file(path: &Path, report: &mut GcReport) -> u64 {
+    let size = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    match std::fs::remove_file(path) {
+        Ok(()) => size,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+        Err(e) => {
+            report.errors.push(format!("{}: {}", path.display(), e));
+            0
+        }
+    }
+}
+
+pub fn run(conn: &mut SqliteConnection) -> anyhow::Result<GcReport> {
+    let mut report = GcReport::default();
+
+    let (expired, released) = db::delete_expired_files(conn)?;
+    report.expired_objects = expired;
+    for path in released {
+        report.expired_bytes += remove_file(Path::new(&path), &mut report);
+    }
+
+    // Blob refcounts should always match the number of objects pointing at them
+    let blobs = crate::schema::blobs::table
+        .select(Blob::as_select())
+        .load(conn)?;
+    let references = crate::schema::objects::table
+        .filter(crate::schema::objects::blob_hash.is_not_null())
+        .group_by(crate::schema::objects::blob_hash)
+        .select((crate::schema::objects::blob_hash.assume_not_null(), diesel::dsl::count_star()))
+        .load::<(String, i64)>(conn)?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+    for blob in blobs {
+        let actual = references.get(&blob.hash).copied().unwrap_or(0) as i32;
+        if actual == blob.refcount {
+            continue;
+        }
+        warn!(
+            "Blob {} has refcount {} but {} references, fixing",
+            blob.hash, blob.refcount, actual
+        );
+        report.fixed_refcounts += 1;
+        use crate::schema::blobs::dsl::*;
+        if actual == 0 {
+            diesel::delete(blobs.find(&blob.hash)).execute(conn)?;
+            report.expired_bytes += remove_file(Path::new(&blob.path), &mut report);
+        } else {
+            diesel::update(blobs.find(&blob.hash))
+                .set(refcount.eq(actual))
+                .execute(conn)?;
+        }
+    }
+
+    let objects = crate::schema::objects::table
+        .select(Object::as_select())
+        .load(conn)?;
+    let mut known = objects
+        .iter()
+        .map(|object| PathBuf::from(&object.path))
+        .collect::<HashSet<_>>();
+    known.extend(
+        crate::schema::blobs::table
+            .select(crate::schema::blobs::path)
+            .load::<String>(conn)?
+            .into_iter()
+            .map(PathBuf::from),
+    );
+    for object in &objects {
+        if !Path::new(&object.path).exists() {
+            warn!("Object {} is missing its file {}", object.id, object.path);
+            report.missing_files.push(object.id);
+        }
+    }
+
+    let now = SystemTime::now();
+    for shard in std::fs::read_dir(storage::objects_dir())? {
+        let shard = shard?;
+        if !shard.file_type()?.is_dir() {
+            continue;
+        }
+        for entry in std::fs::read_dir(shard.path())? {
+            let entry = entry?;
+            let path = entry.path();
+            if known.contains(&path) {
+                continue;
+            }
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            let age = metadata
+                .modified()
+                .ok()
+                .and_then(|modified| now.duration_since(modified).ok())
+                .unwrap_or_default();
+            if !metadata.is_file() || age < ORPHAN_GRACE {
+                continue;
+            }
+            warn!("Removing orphaned file {}", path.display());
+            report.orphaned_bytes += remove_file(&path, &mut report);
+            report.orphaned_files.push(path);
+        }
+    }
+
+    for error in &report.errors {
+        warn!("GC error: {}", error);
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::store_test_object;
+
+    #[test]
+    fn removes_blob_once_every_object_expired() {
+        let mut conn = db::test_connection();
+        let expired = store_test_object("gc content", 1, 1, &mut conn);
+        let live = store_test_object("gc content", 2, db::PINNED, &mut conn);
+
+        let report = run(&mut conn).unwrap();
+        assert_eq!(report.expired_objects, 1);
+        assert_eq!(report.fixed_refcounts, 0);
+        assert!(Path::new(&live.path).exists());
+
+        diesel::update(crate::schema::objects::table.find(live.id))
+            .set(crate::schema::objects::expiry_unix.eq(1))
+            .execute(&mut conn)
+            .unwrap();
+        let report = run(&mut conn).unwrap();
+        assert_eq!(report.expired_objects, 1);
+        assert!(report.errors.is_empty());
+        assert!(!Path::new(&expired.path).exists());
+        let blobs = crate::schema::blobs::table
+            .count()
+            .get_result::<i64>(&mut conn)
+            .unwrap();
+        assert_eq!(blobs, 0);
+    }
+}
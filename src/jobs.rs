@@ -0,0 +1,820 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use diesel::prelude::*;
+use poise::serenity_prelude as serenity;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Notify, oneshot};
+use tracing::{error, info};
+
+use crate::{
+    Data, PostProcessOrchestrator,
+    db::{
+        DatabasePool, Destination, Job, NewCollection, NewJob, NewObject, NewUpload, Object,
+        ObjectMetadata, User, unix_now,
+    },
+    downloader::{DownloadFailure, DownloadedItem, Downloader},
+    gallerydl::GalleryDownloader,
+    limits::{Limits, ResourceClass},
+    pp::FFMpegResizeProcessor,
+    progress::{Progress, ProgressReporter},
+    sharex,
+    ytdlp::YoutubeDownloader,
+};
+
+pub const QUEUED: &str = "queued";
+pub const RUNNING: &str = "running";
+pub const DONE: &str = "done";
+pub const FAILED: &str = "failed";
+pub const CANCELLED: &str = "cancelled";
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum DownloaderKind {
+    Ytdlp,
+    GalleryDl,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum JobSpec {
+    Download {
+        downloader: DownloaderKind,
+        url: String,
+        playlist: bool,
+        max_entries: u32,
+        // Jobs queued before expiry was configurable don't have this
+        #[serde(default)]
+        expiry_unix: Option<i64>,
+    },
+    Compress {
+        object_id: i32,
+        max_size: u64,
+    },
+    Upload {
+        object_id: i32,
+        // None uses the user's default destination
+        #[serde(default)]
+        destination: Option<i32>,
+    },
+}
+
+impl JobSpec {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            JobSpec::Download { .. } => "download",
+            JobSpec::Compress { .. } => "compress",
+            JobSpec::Upload { .. } => "upload",
+        }
+    }
+
+    // Uploads are not retried after a restart since the remote end may already have the file
+    pub fn resumable(kind: &str) -> bool {
+        kind == "download" || kind == "compress"
+    }
+
+    // The resource a job mostly waits on, used to keep one user from occupying every worker
+    pub fn resource_class(kind: &str) -> ResourceClass {
+        match kind {
+            "compress" => ResourceClass::Cpu,
+            "upload" => ResourceClass::Network,
+            _ => ResourceClass::Download,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            JobSpec::Download { url, playlist, .. } if *playlist => format!("Playlist {url}"),
+            JobSpec::Download { url, .. } => url.clone(),
+            JobSpec::Compress {
+                object_id,
+                max_size,
+            } => format!(
+                "Object {object_id} to {}",
+                humansize::format_size(*max_size, humansize::DECIMAL)
+            ),
+            JobSpec::Upload { object_id, .. } => format!("Object {object_id}"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct JobOutput {
+    pub objects: Vec<i32>,
+    pub collection: Option<i32>,
+    pub failures: Vec<DownloadFailure>,
+    pub url: Option<String>,
+    #[serde(default)]
+    pub thumbnail_url: Option<String>,
+}
+
+pub enum CancelResult {
+    Cancelled,
+    NotOwner,
+    NotRunning,
+}
+
+struct TrackedJob {
+    user: u64,
+    progress: ProgressReporter,
+    cancel: Option<oneshot::Sender<()>>,
+    // Cancelled after a worker claimed it but before it started running
+    cancel_requested: bool,
+    waiter: Option<oneshot::Sender<()>>,
+}
+
+// In-memory side of the job queue: progress, cancellation and completion notifications for
+// jobs this process knows about. The jobs table is the source of truth for everything else
+#[derive(Clone, Default)]
+pub struct JobQueue {
+    tracked: Arc<Mutex<HashMap<i32, TrackedJob>>>,
+    wakeup: Arc<Notify>,
+}
+
+pub struct JobWatch {
+    pub id: i32,
+    pub progress: ProgressReporter,
+    done: oneshot::Receiver<()>,
+}
+
+impl JobWatch {
+    pub async fn wait(self) {
+        let _ = self.done.await;
+    }
+}
+
+pub struct JobGuard {
+    receiver: oneshot::Receiver<()>,
+}
+
+impl JobGuard {
+    // Returns None if the job was cancelled. The future is dropped on cancellation, so anything
+    // it owns (child processes spawned with kill_on_drop, temp files) gets cleaned up with it
+    pub async fn run<T>(mut self, fut: impl Future<Output = T>) -> Option<T> {
+        tokio::select! {
+            biased;
+            Ok(()) = &mut self.receiver => None,
+            output = fut => Some(output),
+        }
+    }
+}
+
+pub fn default_expiry_unix() -> i64 {
+    unix_now() + 60 * 60 * 24 * 7
+}
+
+impl JobQueue {
+    pub async fn enqueue(
+        &self,
+        db: &DatabasePool,
+        user: u64,
+        spec: JobSpec,
+    ) -> anyhow::Result<JobWatch> {
+        let now = unix_now();
+        let new_job = NewJob {
+            user: user as i64,
+            kind: spec.kind().to_owned(),
+            spec: serde_json::to_string(&spec)?,
+            status: QUEUED.to_owned(),
+            created_unix: now,
+            updated_unix: now,
+        };
+        let (waiter, done) = oneshot::channel();
+        let progress = ProgressReporter::new();
+        progress.update(Progress::stage("Queued"));
+        let tracked = self.tracked.clone();
+        let tracked_progress = progress.clone();
+        let job = db
+            .get()
+            .await?
+            .interact(move |x| {
+                // Held across the insert so a worker can't start and finish the job before it's
+                // tracked, which would leave nothing to signal the waiter
+                let mut tracked = tracked.lock().unwrap();
+                let job = diesel::insert_into(crate::schema::jobs::table)
+                    .values(&new_job)
+                    .returning(Job::as_returning())
+                    .get_result(x)?;
+                tracked.insert(
+                    job.id,
+                    TrackedJob {
+                        user,
+                        progress: tracked_progress,
+                        cancel: None,
+                        cancel_requested: false,
+                        waiter: Some(waiter),
+                    },
+                );
+                Ok::<_, diesel::result::Error>(job)
+            })
+            .await
+            .unwrap()?;
+        self.wakeup.notify_one();
+        Ok(JobWatch {
+            id: job.id,
+            progress,
+            done,
+        })
+    }
+
+    fn start(&self, id: i32, user: u64) -> (ProgressReporter, JobGuard) {
+        let (cancel, receiver) = oneshot::channel();
+        let mut tracked = self.tracked.lock().unwrap();
+        // Jobs resumed after a restart aren't tracked yet
+        let entry = tracked.entry(id).or_insert_with(|| TrackedJob {
+            user,
+            progress: ProgressReporter::new(),
+            cancel: None,
+            cancel_requested: false,
+            waiter: None,
+        });
+        if entry.cancel_requested {
+            let _ = cancel.send(());
+        } else {
+            entry.cancel = Some(cancel);
+        }
+        entry.progress.update(Progress::stage("Starting"));
+        (entry.progress.clone(), JobGuard { receiver })
+    }
+
+    fn finish(&self, id: i32) -> Option<oneshot::Sender<()>> {
+        self.tracked
+            .lock()
+            .unwrap()
+            .remove(&id)
+            .and_then(|job| job.waiter)
+    }
+
+    pub async fn cancel(
+        &self,
+        db: &DatabasePool,
+        job_id: i32,
+        uid: u64,
+    ) -> anyhow::Result<CancelResult> {
+        {
+            let mut tracked = self.tracked.lock().unwrap();
+            if let Some(job) = tracked.get_mut(&job_id) {
+                if job.user != uid {
+                    return Ok(CancelResult::NotOwner);
+                }
+                if let Some(cancel) = job.cancel.take() {
+                    let _ = cancel.send(());
+                    return Ok(CancelResult::Cancelled);
+                }
+            }
+        }
+        // Not picked up by a worker yet, so it only has to be taken out of the queue
+        let now = unix_now();
+        let updated = db
+            .get()
+            .await?
+            .interact(move |x| {
+                use crate::schema::jobs::dsl::*;
+                diesel::update(
+                    jobs.find(job_id)
+                        .filter(user.eq(uid as i64))
+                        .filter(status.eq(QUEUED)),
+                )
+                .set((status.eq(CANCELLED), updated_unix.eq(now)))
+                .execute(x)
+            })
+            .await
+            .unwrap()?;
+        if updated == 0 {
+            // A worker claimed it in the meantime, it's either starting or already done
+            let mut tracked = self.tracked.lock().unwrap();
+            let Some(job) = tracked.get_mut(&job_id) else {
+                return Ok(CancelResult::NotRunning);
+            };
+            if job.user != uid {
+                return Ok(CancelResult::NotOwner);
+            }
+            match job.cancel.take() {
+                Some(cancel) => {
+                    let _ = cancel.send(());
+                }
+                None => job.cancel_requested = true,
+            }
+            return Ok(CancelResult::Cancelled);
+        }
+        if let Some(waiter) = self.finish(job_id) {
+            let _ = waiter.send(());
+        }
+        Ok(CancelResult::Cancelled)
+    }
+}
+
+// Jobs that were running when the bot went down are either queued again or marked as failed
+pub async fn recover_interrupted(db: &DatabasePool) -> anyhow::Result<Vec<Job>> {
+    let now = unix_now();
+    db.get()
+        .await?
+        .interact(move |x| {
+            use crate::schema::jobs::dsl::*;
+            x.immediate_transaction(|x| {
+                let interrupted = jobs
+                    .filter(status.eq(RUNNING))
+                    .select(Job::as_select())
+                    .load(x)?;
+                let mut failed = vec![];
+                for job in interrupted {
+                    if JobSpec::resumable(&job.kind) {
+                        info!("Resuming interrupted job {}", job.id);
+                        diesel::update(jobs.find(job.id))
+                            .set((status.eq(QUEUED), updated_unix.eq(now)))
+                            .execute(x)?;
+                    } else {
+                        info!("Marking interrupted job {} as failed", job.id);
+                        failed.push(
+                            diesel::update(jobs.find(job.id))
+                                .set((
+                                    status.eq(FAILED),
+                                    error.eq("Interrupted by a bot restart"),
+                                    updated_unix.eq(now),
+                                ))
+                                .returning(Job::as_returning())
+                                .get_result(x)?,
+                        );
+                    }
+                }
+                Ok(failed)
+            })
+        })
+        .await
+        .unwrap()
+}
+
+// Oldest queued job whose user has a free per-user slot, jobs of users at their limit wait
+// in the queue instead of tying up a worker
+async fn claim_next(db: &DatabasePool, limits: &Limits) -> anyhow::Result<Option<Job>> {
+    let now = unix_now();
+    let limits = limits.clone();
+    db.get()
+        .await?
+        .interact(move |x| {
+            use crate::schema::jobs::dsl::*;
+            x.immediate_transaction(|x| {
+                let queued = jobs
+                    .filter(status.eq(QUEUED))
+                    .order_by(id.asc())
+                    .select((id, user, kind))
+                    .load::<(i32, i64, String)>(x)?;
+                let running = jobs
+                    .filter(status.eq(RUNNING))
+                    .select((user, kind))
+                    .load::<(i64, String)>(x)?;
+                let next = queued.into_iter().find(|(_, job_user, job_kind)| {
+                    let class = JobSpec::resource_class(job_kind);
+                    let busy = running
+                        .iter()
+                        .filter(|(running_user, running_kind)| {
+                            running_user == job_user
+                                && JobSpec::resource_class(running_kind) == class
+                        })
+                        .count();
+                    busy < limits.per_user_slots(class)
+                });
+                let Some((next, _, _)) = next else {
+                    return Ok(None);
+                };
+                let job = diesel::update(jobs.find(next))
+                    .set((status.eq(RUNNING), updated_unix.eq(now)))
+                    .returning(Job::as_returning())
+                    .get_result(x)?;
+                Ok(Some(job))
+            })
+        })
+        .await
+        .unwrap()
+}
+
+pub async fn spawn_workers(data: Data, http: Arc<serenity::Http>) -> anyhow::Result<()> {
+    for job in recover_interrupted(&data.db).await? {
+        if let Err(e) = notify_by_dm(&data, &http, &job).await {
+            error!("Failed to notify user about job {}: {:?}", job.id, e);
+        }
+    }
+    let workers = std::env::var("JOB_WORKERS")
+        .ok()
+        .and_then(|workers| workers.parse().ok())
+        .unwrap_or(4);
+    info!("Starting {} job workers", workers);
+    for _ in 0..workers {
+        tokio::spawn(worker(data.clone(), http.clone()));
+    }
+    Ok(())
+}
+
+async fn worker(data: Data, http: Arc<serenity::Http>) {
+    loop {
+        match claim_next(&data.db, &data.limits).await {
+            Ok(Some(job)) => {
+                let id = job.id;
+                if let Err(e) = run_job(&data, &http, job).await {
+                    error!("Job {} could not be completed: {:?}", id, e);
+                }
+            }
+            Ok(None) => {
+                let _ = tokio::time::timeout(Duration::from_secs(30), data.jobs.wakeup.notified())
+                    .await;
+            }
+            Err(e) => {
+                error!("Failed to claim a job: {:?}", e);
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            }
+        }
+    }
+}
+
+async fn run_job(data: &Data, http: &serenity::Http, job: Job) -> anyhow::Result<()> {
+    info!("Running job {} ({})", job.id, job.kind);
+    let (progress, guard) = data.jobs.start(job.id, job.user as u64);
+    let result = match serde_json::from_str::<JobSpec>(&job.spec) {
+        Ok(spec) => guard.run(execute(data, job.user, spec, &progress)).await,
+        Err(e) => Some(Err(e.into())),
+    };
+    let (new_status, new_output, new_error) = match result {
+        Some(Ok(output)) => (DONE, Some(serde_json::to_string(&output)?), None),
+        Some(Err(e)) => {
+            error!("Job {} failed: {:?}", job.id, e);
+            (FAILED, None, Some(e.to_string()))
+        }
+        None => (CANCELLED, None, None),
+    };
+    let now = unix_now();
+    let job_id = job.id;
+    let job = data
+        .db
+        .get()
+        .await?
+        .interact(move |x| {
+            use crate::schema::jobs::dsl::*;
+            diesel::update(jobs.find(job_id))
+                .set((
+                    status.eq(new_status),
+                    output.eq(new_output),
+                    error.eq(new_error),
+                    updated_unix.eq(now),
+                ))
+                .returning(Job::as_returning())
+                .get_result(x)
+        })
+        .await
+        .unwrap()?;
+    // A user slot just opened up, which may unblock a skipped job
+    data.jobs.wakeup.notify_one();
+    let waited_on = data
+        .jobs
+        .finish(job.id)
+        .is_some_and(|waiter| waiter.send(()).is_ok());
+    // Nobody is waiting on this job (e.g. it was resumed after a restart), so report back by DM
+    if !waited_on {
+        notify_by_dm(data, http, &job).await?;
+    }
+    Ok(())
+}
+
+pub async fn notify_by_dm(data: &Data, http: &serenity::Http, job: &Job) -> anyhow::Result<()> {
+    let reply = crate::job_reply(data, job).await?;
+    serenity::UserId::new(job.user as u64)
+        .direct_message(http, crate::reply_to_message(reply))
+        .await?;
+    Ok(())
+}
+
+async fn execute(
+    data: &Data,
+    uid: i64,
+    spec: JobSpec,
+    progress: &ProgressReporter,
+) -> anyhow::Result<JobOutput> {
+    match spec {
+        JobSpec::Download {
+            downloader,
+            url,
+            playlist,
+            max_entries,
+            expiry_unix,
+        } => {
+            let user = data
+                .db
+                .get()
+                .await?
+                .interact(move |x| User::get(uid, x))
+                .await
+                .unwrap()?;
+            anyhow::ensure!(
+                user.bytes_remaining() > 0,
+                "Storage quota exceeded ({} of {} used)",
+                humansize::format_size(user.bytes_used as u64, humansize::DECIMAL),
+                humansize::format_size(user.quota() as u64, humansize::DECIMAL)
+            );
+            // Single files bigger than what's left are skipped by the downloaders themselves
+            let max_filesize = Some(user.bytes_remaining() as u64);
+            let downloader: Box<dyn Downloader + Send + Sync> = match downloader {
+                DownloaderKind::Ytdlp => Box::new(YoutubeDownloader {
+                    playlist,
+                    max_entries,
+                    max_filesize,
+                }),
+                DownloaderKind::GalleryDl => Box::new(GalleryDownloader { max_filesize }),
+            };
+            let permit = data
+                .limits
+                .acquire(ResourceClass::Download, uid as u64, progress)
+                .await?;
+            let output = downloader.download(url.clone(), progress).await?;
+            drop(permit);
+            progress.update(Progress::stage("Storing"));
+            let mut objects = vec![];
+            let mut failures = output.failures;
+            let expiry_unix = expiry_unix.unwrap_or_else(default_expiry_unix);
+            for item in output.items {
+                let name = item.name.clone();
+                // Keep what fit in the quota instead of throwing away the whole download
+                match store_item(data, uid, item, expiry_unix).await {
+                    Ok(object) => objects.push(object.id),
+                    Err(e) if !objects.is_empty() || playlist => failures.push(DownloadFailure {
+                        entry: name,
+                        reason: e.to_string(),
+                    }),
+                    Err(e) => return Err(e),
+                }
+            }
+            anyhow::ensure!(
+                !objects.is_empty(),
+                "Nothing could be stored: {}",
+                failures.first().map(|f| f.reason.as_str()).unwrap_or("unknown error")
+            );
+            let collection = if playlist {
+                let new_collection = NewCollection {
+                    user: uid,
+                    name: output.title.unwrap_or(url.clone()),
+                    source_url: Some(url),
+                };
+                let object_ids = objects.clone();
+                let collection = data
+                    .db
+                    .get()
+                    .await?
+                    .interact(move |x| new_collection.create_with_objects(object_ids, x))
+                    .await
+                    .unwrap()?;
+                Some(collection.id)
+            } else {
+                None
+            };
+            Ok(JobOutput {
+                objects,
+                collection,
+                failures,
+                ..Default::default()
+            })
+        }
+        JobSpec::Compress {
+            object_id,
+            max_size,
+        } => {
+            let object = load_object(data, object_id).await?;
+            let user = data
+                .db
+                .get()
+                .await?
+                .interact(move |x| User::get(uid, x))
+                .await
+                .unwrap()?;
+            let mut pp_orchestrator =
+                PostProcessOrchestrator::new(user, object, data.clone(), progress.clone());
+            let ffmpeg = FFMpegResizeProcessor { max_size };
+            pp_orchestrator.add_post_processor(&ffmpeg, true);
+            println!("Running compress pass");
+            pp_orchestrator.process().await?;
+            println!("Finished compress pass");
+            Ok(JobOutput {
+                objects: vec![pp_orchestrator.object.id],
+                ..Default::default()
+            })
+        }
+        JobSpec::Upload {
+            object_id,
+            destination,
+        } => {
+            let object = load_object(data, object_id).await?;
+            let destination = data
+                .db
+                .get()
+                .await?
+                .interact(move |x| Destination::find(uid, destination, x))
+                .await
+                .unwrap()?
+                .ok_or_else(|| {
+                    anyhow::anyhow!("That destination doesn't exist, add one with /destination add")
+                })?;
+            let uploader =
+                sharex::CustomUploader::from_stored(&destination.json, &data.credentials)
+                    .map_err(|e| {
+                        anyhow::anyhow!("Error loading the config of {}: {}", destination.name, e)
+                    })?;
+            let _permit = data
+                .limits
+                .acquire(ResourceClass::Network, uid as u64, progress)
+                .await?;
+            progress.update(Progress::stage("Uploading"));
+            let uploaded = uploader
+                .upload(Path::new(&object.path), &object.file_name())
+                .await?;
+            info!("Uploaded object {} to {}", object.id, uploaded.url);
+            // Deletion URLs let anyone delete the remote copy, so they're stored like credentials
+            let upload = NewUpload {
+                object_id: Some(object.id),
+                object_name: object.name.clone(),
+                user: uid,
+                destination_name: destination.name,
+                url: uploaded.url.clone(),
+                deletion_url: uploaded
+                    .deletion_url
+                    .filter(|deletion_url| !deletion_url.is_empty())
+                    .map(|deletion_url| data.credentials.encrypt(&deletion_url))
+                    .transpose()?,
+                created_unix: unix_now(),
+            };
+            data.db
+                .get()
+                .await?
+                .interact(move |x| {
+                    diesel::insert_into(crate::schema::uploads::table)
+                        .values(upload)
+                        .execute(x)
+                })
+                .await
+                .unwrap()?;
+            Ok(JobOutput {
+                url: Some(uploaded.url),
+                thumbnail_url: uploaded.thumbnail_url,
+                ..Default::default()
+            })
+        }
+    }
+}
+
+async fn load_object(data: &Data, object_id: i32) -> anyhow::Result<Object> {
+    let object = data
+        .db
+        .get()
+        .await?
+        .interact(move |x| {
+            use crate::schema::objects::dsl::*;
+            objects.find(object_id).select(Object::as_select()).first(x)
+        })
+        .await
+        .unwrap()?;
+    Ok(object)
+}
+
+async fn store_item(
+    data: &Data,
+    user: i64,
+    item: DownloadedItem,
+    expiry_unix: i64,
+) -> anyhow::Result<Object> {
+    info!(
+        "Storing {} ({}) from {} [{}:{}]",
+        item.name,
+        item.mime_type,
+        item.source.url,
+        item.source.extractor.as_deref().unwrap_or("unknown"),
+        item.source.id.as_deref().unwrap_or("unknown"),
+    );
+    let size = std::fs::metadata(&item.file)?.len() as i64;
+    let source_url = crate::urls::canonicalize(&item.source.url);
+    let extractor_id = item
+        .source
+        .extractor
+        .zip(item.source.id)
+        .map(|(extractor, id)| format!("{extractor}:{id}"));
+    let object = NewObject {
+        path: String::new(),
+        name: item.name,
+        size,
+        expiry_unix,
+        user,
+        blob_hash: None,
+        source_url: Some(source_url),
+        extractor_id,
+        created_unix: unix_now(),
+    };
+    let metadata = item.metadata;
+    let object = data
+        .db
+        .get()
+        .await?
+        .interact(move |x| {
+            x.transaction(|x| {
+                let object = crate::storage::store(object, item.file, x)?;
+                if let Some(metadata) = metadata {
+                    diesel::insert_into(crate::schema::object_metadata::table)
+                        .values(ObjectMetadata {
+                            object_id: object.id,
+                            ..metadata
+                        })
+                        .execute(x)?;
+                }
+                Ok::<_, anyhow::Error>(object)
+            })
+        })
+        .await
+        .unwrap()?;
+    Ok(object)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn insert_job(db: &DatabasePool, uid: i64, job_kind: &str) -> i32 {
+        let new_job = NewJob {
+            user: uid,
+            kind: job_kind.to_owned(),
+            spec: String::new(),
+            status: QUEUED.to_owned(),
+            created_unix: 0,
+            updated_unix: 0,
+        };
+        db.get()
+            .await
+            .unwrap()
+            .interact(move |x| {
+                diesel::insert_into(crate::schema::jobs::table)
+                    .values(&new_job)
+                    .returning(crate::schema::jobs::id)
+                    .get_result(x)
+            })
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    async fn claim(db: &DatabasePool, limits: &Limits) -> Option<i32> {
+        claim_next(db, limits).await.unwrap().map(|job| job.id)
+    }
+
+    #[tokio::test]
+    async fn claims_oldest_first() {
+        let db = crate::db::test_pool().await;
+        let limits = Limits::default();
+        let first = insert_job(&db, 1, "download").await;
+        let second = insert_job(&db, 2, "compress").await;
+        let third = insert_job(&db, 3, "upload").await;
+        assert_eq!(claim(&db, &limits).await, Some(first));
+        assert_eq!(claim(&db, &limits).await, Some(second));
+        assert_eq!(claim(&db, &limits).await, Some(third));
+        assert_eq!(claim(&db, &limits).await, None);
+    }
+
+    #[tokio::test]
+    async fn skips_users_at_their_limit() {
+        let db = crate::db::test_pool().await;
+        let limits = Limits::default();
+        let slots = limits.per_user_slots(ResourceClass::Download);
+        let mut busy_user = vec![];
+        for _ in 0..=slots {
+            busy_user.push(insert_job(&db, 1, "download").await);
+        }
+        let other_user = insert_job(&db, 2, "download").await;
+        // Another class has its own slots
+        let other_class = insert_job(&db, 1, "upload").await;
+        for job in &busy_user[..slots] {
+            assert_eq!(claim(&db, &limits).await, Some(*job));
+        }
+        assert_eq!(claim(&db, &limits).await, Some(other_user));
+        assert_eq!(claim(&db, &limits).await, Some(other_class));
+        assert_eq!(claim(&db, &limits).await, None);
+    }
+
+    #[tokio::test]
+    async fn cancelled_jobs_are_not_claimed() {
+        let db = crate::db::test_pool().await;
+        let limits = Limits::default();
+        let queue = JobQueue::default();
+        let spec = JobSpec::Compress {
+            object_id: 1,
+            max_size: 1,
+        };
+        let watch = queue.enqueue(&db, 1, spec).await.unwrap();
+        assert!(matches!(
+            queue.cancel(&db, watch.id, 2).await.unwrap(),
+            CancelResult::NotOwner
+        ));
+        assert!(matches!(
+            queue.cancel(&db, watch.id, 1).await.unwrap(),
+            CancelResult::Cancelled
+        ));
+        watch.wait().await;
+        assert_eq!(claim(&db, &limits).await, None);
+    }
+}
@@ -0,0 +1,179 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::progress::{Progress, ProgressReporter};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ResourceClass {
+    // yt-dlp / gallery-dl runs
+    Download,
+    // ffmpeg encodes and anything else that pins the CPU
+    Cpu,
+    // Uploads to remote hosts
+    Network,
+}
+
+impl ResourceClass {
+    fn env_prefix(&self) -> &'static str {
+        match self {
+            ResourceClass::Download => "DOWNLOAD",
+            ResourceClass::Cpu => "ENCODE",
+            ResourceClass::Network => "UPLOAD",
+        }
+    }
+
+    fn default_slots(&self) -> (usize, usize) {
+        match self {
+            ResourceClass::Download => (3, 1),
+            ResourceClass::Cpu => (1, 1),
+            ResourceClass::Network => (4, 2),
+        }
+    }
+}
+
+fn env_slots(name: String, default: usize) -> usize {
+    std::env::var(name)
+        .ok()
+        .and_then(|slots| slots.parse().ok())
+        .filter(|slots| *slots > 0)
+        .unwrap_or(default)
+}
+
+struct ClassLimiter {
+    global: Arc<Semaphore>,
+    per_user_slots: usize,
+    users: Mutex<HashMap<u64, Arc<Semaphore>>>,
+    // Tickets of everyone waiting for a slot, in arrival order
+    waiting: Mutex<BTreeSet<u64>>,
+    next_ticket: AtomicU64,
+}
+
+impl ClassLimiter {
+    fn from_env(class: ResourceClass) -> Self {
+        let (global, per_user) = class.default_slots();
+        let prefix = class.env_prefix();
+        let global = env_slots(format!("{prefix}_SLOTS"), global);
+        let per_user_slots = env_slots(format!("{prefix}_SLOTS_PER_USER"), per_user);
+        Self {
+            global: Arc::new(Semaphore::new(global)),
+            per_user_slots,
+            users: Mutex::new(HashMap::new()),
+            waiting: Mutex::new(BTreeSet::new()),
+            next_ticket: AtomicU64::new(0),
+        }
+    }
+
+    fn user_semaphore(&self, user: u64) -> Arc<Semaphore> {
+        let mut users = self.users.lock().unwrap();
+        // Permits and waiters hold a clone, so anything else is idle and can go
+        users.retain(|_, semaphore| Arc::strong_count(semaphore) > 1);
+        users
+            .entry(user)
+            .or_insert_with(|| Arc::new(Semaphore::new(self.per_user_slots)))
+            .clone()
+    }
+
+    fn position(&self, ticket: u64) -> usize {
+        self.waiting.lock().unwrap().range(..ticket).count() + 1
+    }
+}
+
+pub struct Permit {
+    _user: OwnedSemaphorePermit,
+    _global: OwnedSemaphorePermit,
+}
+
+// Removes the ticket from the waiting set even if the acquiring future is dropped (e.g. on cancel)
+struct Ticket<'a> {
+    limiter: &'a ClassLimiter,
+    id: u64,
+}
+
+impl Drop for Ticket<'_> {
+    fn drop(&mut self) {
+        self.limiter.waiting.lock().unwrap().remove(&self.id);
+    }
+}
+
+#[derive(Clone)]
+pub struct Limits {
+    classes: Arc<HashMap<ResourceClass, ClassLimiter>>,
+}
+
+impl Limits {
+    pub fn from_env() -> Self {
+        let classes = [
+            ResourceClass::Download,
+            ResourceClass::Cpu,
+            ResourceClass::Network,
+        ]
+        .into_iter()
+        .map(|class| (class, ClassLimiter::from_env(class)))
+        .collect();
+        Self {
+            classes: Arc::new(classes),
+        }
+    }
+
+    pub fn per_user_slots(&self, class: ResourceClass) -> usize {
+        self.classes[&class].per_user_slots
+    }
+
+    // Waits for both a per-user and a global slot, reporting the position in line while waiting
+    pub async fn acquire(
+        &self,
+        class: ResourceClass,
+        user: u64,
+        progress: &ProgressReporter,
+    ) -> anyhow::Result<Permit> {
+        let limiter = &self.classes[&class];
+        let ticket = Ticket {
+            limiter,
+            id: limiter.next_ticket.fetch_add(1, Ordering::Relaxed),
+        };
+        limiter.waiting.lock().unwrap().insert(ticket.id);
+
+        let acquire = async {
+            let user_permit = limiter.user_semaphore(user).acquire_owned().await?;
+            let global_permit = limiter.global.clone().acquire_owned().await?;
+            Ok::<_, anyhow::Error>(Permit {
+                _user: user_permit,
+                _global: global_permit,
+            })
+        };
+        tokio::pin!(acquire);
+        let mut last_position = 0;
+        loop {
+            tokio::select! {
+                permit = &mut acquire => {
+                    if last_position != 0 {
+                        progress.update(Progress::stage("Starting"));
+                    }
+                    return permit;
+                }
+                _ = tokio::time::sleep(Duration::from_secs(1)) => {}
+            }
+            let position = limiter.position(ticket.id);
+            if position != last_position {
+                last_position = position;
+                progress.update(Progress::stage(format!(
+                    "Waiting for a free slot (position {position})"
+                )));
+            }
+        }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self::from_env()
+    }
+}
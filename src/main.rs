@@ -1,54 +1,81 @@
 use std::{
     any::TypeId,
     collections::{HashMap, VecDeque},
-    time::{Duration, SystemTime, UNIX_EPOCH},
+    time::{Duration, SystemTime},
 };
 
 use anyhow::Error;
-use db::{NewObject, Object, SharexConfig, User};
-use downloader::Downloader;
+use browser::{BrowserState, ObjectSort};
+use search::{MediaType, SearchFilters};
+use sharing::{Access, ShareAccess};
+use db::{Object, ObjectMetadata, User, unix_now};
 use poise::{
     CreateReply,
     serenity_prelude::{
         self as serenity, ComponentInteractionDataKind, CreateActionRow, CreateAttachment, CreateEmbed, CreateInteractionResponseFollowup, CreateSelectMenu,
-        CreateSelectMenuOption, Interaction,
+        CreateSelectMenuOption, Interaction, Mentionable,
     },
 };
-use pp::{FFMpegResizeProcessor, PostProcessInput, PostProcessor};
+use limits::Limits;
+use jobs::{CancelResult, DownloaderKind, JobOutput, JobQueue, JobSpec, JobWatch};
+use pp::{PostProcessInput, PostProcessor};
+use progress::{ProgressReporter, with_progress};
 use tokio_schedule::Job;
-use tracing::info;
+use tracing::{error, info};
 use ytdlp::YoutubeDownloader;
+mod browser;
+mod credentials;
 mod db;
 mod downloader;
+mod fileserver;
 mod gallerydl;
+mod gc;
+mod jobs;
+mod limits;
 mod pp;
+mod progress;
 mod schema;
+mod search;
+mod sharing;
 mod sharex;
+mod storage;
+mod urls;
 mod ytdlp;
 
 #[derive(Clone)]
 pub struct Data {
     db: db::DatabasePool,
+    jobs: JobQueue,
+    limits: Limits,
+    links: Option<fileserver::Links>,
+    credentials: credentials::Credentials,
 } // User data, which is stored and accessible in all command invocations
 
 type Context<'a> = poise::Context<'a, Data, Error>;
 
 pub struct PostProcessOrchestrator<'a> {
-    user: poise::serenity_prelude::User,
+    user: User,
     object: Object,
     data: Data,
     post_processors: HashMap<TypeId, &'a (dyn PostProcessor + Sync + Send)>,
     postprocessors_to_run: Vec<TypeId>,
+    progress: ProgressReporter,
 }
 
 impl<'a> PostProcessOrchestrator<'a> {
-    pub fn new(user: poise::serenity_prelude::User, object: Object, data: Data) -> Self {
+    pub fn new(
+        user: User,
+        object: Object,
+        data: Data,
+        progress: ProgressReporter,
+    ) -> Self {
         Self {
             user,
             object,
             data,
             post_processors: HashMap::new(),
             postprocessors_to_run: vec![],
+            progress,
         }
     }
 
@@ -66,21 +93,12 @@ impl<'a> PostProcessOrchestrator<'a> {
     }
 
     pub async fn process(&mut self) -> Result<(), anyhow::Error> {
-        let uid = self.user.id.get() as i64;
-        let username = self.user.name.clone();
-        let user = self
-            .data
-            .db
-            .get()
-            .await?
-            .interact(move |x| User::get_or_create(uid, username, x))
-            .await
-            .unwrap()?;
         let mut input = PostProcessInput {
             file: self.object.clone(),
             previous_passes: vec![],
             data: self.data.clone(),
-            user,
+            user: self.user.clone(),
+            progress: self.progress.clone(),
         };
         let mut queue = self
             .postprocessors_to_run
@@ -93,7 +111,17 @@ impl<'a> PostProcessOrchestrator<'a> {
             let mut previous_passes = input.previous_passes.clone();
             let user = input.user.clone();
             let data = input.data.clone();
+            let progress = input.progress.clone();
             if post_processor.check(&input).await {
+                let _permit = self
+                    .data
+                    .limits
+                    .acquire(
+                        post_processor.resource_class(),
+                        self.user.snowflake as u64,
+                        &self.progress,
+                    )
+                    .await?;
                 let output = post_processor.process(input).await?;
                 previous_passes.push(typeid);
                 input = PostProcessInput {
@@ -101,6 +129,7 @@ impl<'a> PostProcessOrchestrator<'a> {
                     previous_passes,
                     user,
                     data,
+                    progress,
                 };
                 queue.extend(output.additional_passes);
             }
@@ -110,156 +139,2636 @@ impl<'a> PostProcessOrchestrator<'a> {
     }
 }
 
-fn embed_object(object: Object) -> Result<CreateReply, Error> {
-    let object_expiry_time =
-        SystemTime::UNIX_EPOCH + Duration::from_secs(object.expiry_unix as u64);
-    let days_until_expiry = object_expiry_time
-        .duration_since(SystemTime::now())
-        .unwrap()
-        .as_secs()
-        / (60 * 60 * 24);
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{hours}:{minutes:02}:{seconds:02}")
+    } else {
+        format!("{minutes}:{seconds:02}")
+    }
+}
+
+fn add_metadata_fields(mut embed: CreateEmbed, metadata: ObjectMetadata) -> CreateEmbed {
+    if let Some(url) = metadata.original_url {
+        embed = embed.url(url);
+    }
+    if let Some(uploader) = metadata.uploader {
+        embed = embed.field("Uploader", uploader, true);
+    }
+    // yt-dlp reports upload dates as YYYYMMDD
+    if let Some(date) = metadata.upload_date.filter(|date| date.len() == 8) {
+        embed = embed.field(
+            "Uploaded",
+            format!("{}-{}-{}", &date[..4], &date[4..6], &date[6..]),
+            true,
+        );
+    }
+    if let Some(duration) = metadata.duration {
+        embed = embed.field("Duration", format_duration(duration), true);
+    }
+    if let (Some(width), Some(height)) = (metadata.width, metadata.height) {
+        embed = embed.field("Resolution", format!("{width}x{height}"), true);
+    }
+    let codecs = [metadata.vcodec, metadata.acodec]
+        .into_iter()
+        .flatten()
+        .filter(|codec| codec != "none")
+        .collect::<Vec<_>>();
+    if !codecs.is_empty() {
+        embed = embed.field("Codecs", codecs.join(" / "), true);
+    }
+    if let Some(extractor) = metadata.extractor {
+        embed = embed.field("Source", extractor, true);
+    }
+    if let Some(description) = metadata.description.filter(|d| !d.trim().is_empty()) {
+        let mut snippet: String = description.chars().take(300).collect();
+        if snippet.len() < description.len() {
+            snippet.push_str("...");
+        }
+        embed = embed.field("Description", snippet, false);
+    }
+    embed
+}
+
+// The viewer is whoever the embed is for, only their own uploads are listed in it
+async fn embed_object(data: &Data, object: Object, viewer: i64) -> Result<CreateReply, Error> {
+    let oid = object.id;
+    let (metadata, tags, collections, uploads) = data
+        .db
+        .get()
+        .await?
+        .interact(move |x| {
+            use diesel::prelude::*;
+            let metadata = crate::schema::object_metadata::table
+                .find(oid)
+                .select(ObjectMetadata::as_select())
+                .first(x)
+                .optional()?;
+            let tags = db::object_tags(oid, x)?;
+            // Collections are private to their owner, sharees only see their own
+            let collections = crate::schema::collections::table
+                .inner_join(crate::schema::collection_objects::table)
+                .filter(crate::schema::collection_objects::object_id.eq(oid))
+                .filter(crate::schema::collections::user.eq(viewer))
+                .select(db::Collection::as_select())
+                .load(x)?;
+            let uploads = db::Upload::for_object(oid, viewer, x)?;
+            Ok::<_, diesel::result::Error>((metadata, tags, collections, uploads))
+        })
+        .await
+        .unwrap()?;
+    let expires = if object.expiry_unix == db::PINNED {
+        "Never (pinned)".to_owned()
+    } else {
+        let object_expiry_time =
+            SystemTime::UNIX_EPOCH + Duration::from_secs(object.expiry_unix as u64);
+        let days_until_expiry = object_expiry_time
+            .duration_since(SystemTime::now())
+            .unwrap_or_default()
+            .as_secs()
+            / (60 * 60 * 24);
+        format!("In {days_until_expiry} days")
+    };
+    let embed = CreateEmbed::new()
+        .title(object.name)
+        .description(humansize::format_size(
+            object.size as u64,
+            humansize::DECIMAL,
+        ))
+        .color(serenity::Color::from_rgb(0, 0, 255))
+        .field("Expires", expires, false);
+    let mut embed = match metadata {
+        Some(metadata) => add_metadata_fields(embed, metadata),
+        None => embed,
+    };
+    if !tags.is_empty() {
+        let tags = tags.iter().map(|tag| format!("`{tag}`")).collect::<Vec<_>>();
+        embed = embed.field("Tags", tags.join(" "), false);
+    }
+    if !collections.is_empty() {
+        let collections = collections
+            .iter()
+            .map(|collection| format!("`[{}]` {}", collection.id, collection.name))
+            .collect::<Vec<_>>();
+        embed = embed.field("Collections", collections.join("\n"), false);
+    }
+    if !uploads.is_empty() {
+        embed = embed.field("Uploads", upload_history(&uploads), false);
+    }
+    let mut options = vec![
+        CreateSelectMenuOption::new("Upload to discord", "upload"),
+        CreateSelectMenuOption::new("Delete", "delete"),
+        CreateSelectMenuOption::new("Compress to discord size (10mb)", "compress"),
+        CreateSelectMenuOption::new("Compress to discord size (50mb nitro)", "compress50"),
+        CreateSelectMenuOption::new("Extend 7 days", "extend"),
+        CreateSelectMenuOption::new("Pin forever", "pin"),
+        CreateSelectMenuOption::new("Add to collection", "collection"),
+    ];
+    if data.links.is_some() {
+        options.push(CreateSelectMenuOption::new("Get direct link", "link"));
+    }
+    // Destinations are picked in an ephemeral reply since this menu is usually public
+    options.push(CreateSelectMenuOption::new("Upload to...", "upload_to"));
+    if uploads.iter().any(|upload| upload.can_delete(viewer)) {
+        options.push(CreateSelectMenuOption::new("Delete remote copy", "delete_remote"));
+    }
+    options.push(CreateSelectMenuOption::new("--", "--"));
+    let dropdown = CreateSelectMenu::new(
+        format!("Object:{}", object.id),
+        serenity::CreateSelectMenuKind::String { options },
+    );
+    let action_row = CreateActionRow::SelectMenu(dropdown);
+    Ok(CreateReply {
+        reply: true,
+        components: Some(vec![action_row]),
+        embeds: vec![embed],
+        ..Default::default()
+    })
+}
+
+async fn embed_objects(data: &Data, objects: Vec<Object>, viewer: i64) -> Result<CreateReply, Error> {
+    if objects.len() == 1 {
+        return embed_object(data, objects.into_iter().next().unwrap(), viewer).await;
+    }
+    let total_size: i64 = objects.iter().map(|object| object.size).sum();
+    let mut listing = String::new();
+    for object in &objects {
+        let line = format!(
+            "`[{}]` {} ({})\n",
+            object.id,
+            object.name,
+            humansize::format_size(object.size as u64, humansize::DECIMAL)
+        );
+        // Embed descriptions are capped at 4096 characters
+        if listing.len() + line.len() > 4000 {
+            listing.push_str("...");
+            break;
+        }
+        listing.push_str(&line);
+    }
+    let embed = CreateEmbed::new()
+        .title(format!(
+            "{} objects ({})",
+            objects.len(),
+            humansize::format_size(total_size as u64, humansize::DECIMAL)
+        ))
+        .description(listing)
+        .color(serenity::Color::from_rgb(0, 0, 255));
+    // Select menus hold at most 25 options
+    let options = objects
+        .iter()
+        .take(25)
+        .map(|object| {
+            let label: String = object.name.chars().take(100).collect();
+            CreateSelectMenuOption::new(label, object.id.to_string()).description(
+                humansize::format_size(object.size as u64, humansize::DECIMAL),
+            )
+        })
+        .collect();
+    let dropdown = CreateSelectMenu::new(
+        "OpenObject",
+        serenity::CreateSelectMenuKind::String { options },
+    )
+    .placeholder("Open object");
+    Ok(CreateReply {
+        reply: true,
+        components: Some(vec![CreateActionRow::SelectMenu(dropdown)]),
+        embeds: vec![embed],
+        ..Default::default()
+    })
+}
+
+// Select menus hold at most 25 options
+const MAX_PICKER_DESTINATIONS: usize = 25;
+
+fn upload_line(upload: &db::Upload) -> String {
+    match upload.deleted_unix {
+        None => format!(
+            "[{}]({}) <t:{}:R>",
+            upload.destination_name, upload.url, upload.created_unix
+        ),
+        Some(deleted) => format!("~~{}~~ deleted <t:{}:R>", upload.destination_name, deleted),
+    }
+}
+
+// Embed field values are capped at 1024 characters
+fn upload_history(uploads: &[db::Upload]) -> String {
+    let mut history = String::new();
+    for (i, upload) in uploads.iter().enumerate() {
+        let line = format!("{}\n", upload_line(upload));
+        if history.len() + line.len() > 1000 {
+            history.push_str(&format!("and {} more", uploads.len() - i));
+            break;
+        }
+        history.push_str(&line);
+    }
+    history
+}
+
+fn cancel_button(job_id: i32) -> CreateActionRow {
+    CreateActionRow::Buttons(vec![
+        serenity::CreateButton::new(format!("Cancel:{job_id}"))
+            .label("Cancel")
+            .style(serenity::ButtonStyle::Danger),
+    ])
+}
+
+fn reply_to_message(reply: CreateReply) -> serenity::CreateMessage {
+    serenity::CreateMessage::new()
+        .content(reply.content.unwrap_or_default())
+        .embeds(reply.embeds)
+        .components(reply.components.unwrap_or_default())
+}
+
+fn reply_to_followup(reply: CreateReply) -> CreateInteractionResponseFollowup {
+    CreateInteractionResponseFollowup::new()
+        .content(reply.content.unwrap_or_default())
+        .embeds(reply.embeds)
+        .components(reply.components.unwrap_or_default())
+}
+
+fn ephemeral(message: impl Into<String>) -> serenity::CreateInteractionResponse {
+    serenity::CreateInteractionResponse::Message(
+        serenity::CreateInteractionResponseMessage::new()
+            .content(message)
+            .ephemeral(true),
+    )
+}
+
+// Renders the final state of a job, used both for the in-progress message and DM notifications
+async fn job_reply(data: &Data, job: &db::Job) -> Result<CreateReply, Error> {
+    let reply = match job.status.as_str() {
+        jobs::DONE => {
+            let output: JobOutput = serde_json::from_str(job.output.as_deref().unwrap_or("{}"))?;
+            if let Some(url) = output.url {
+                return Ok(CreateReply::default().content(url).components(vec![]));
+            }
+            let object_ids = output.objects.clone();
+            let collection_id = output.collection;
+            let (objects, collection) = data
+                .db
+                .get()
+                .await?
+                .interact(move |x| {
+                    use diesel::prelude::*;
+                    let objects = crate::schema::objects::table
+                        .filter(crate::schema::objects::id.eq_any(object_ids))
+                        .select(Object::as_select())
+                        .load(x)?;
+                    let collection = match collection_id {
+                        Some(collection_id) => crate::schema::collections::table
+                            .find(collection_id)
+                            .select(db::Collection::as_select())
+                            .first(x)
+                            .optional()?,
+                        None => None,
+                    };
+                    Ok::<_, diesel::result::Error>((objects, collection))
+                })
+                .await
+                .unwrap()?;
+            if objects.is_empty() {
+                return Ok(CreateReply::default()
+                    .content("The job finished, but its objects no longer exist")
+                    .components(vec![]));
+            }
+            let mut reply = embed_objects(data, objects, job.user).await?;
+            let mut embed = reply.embeds.remove(0);
+            if let Some(collection) = collection {
+                embed = embed.field(
+                    "Collection",
+                    format!("`[{}]` {}", collection.id, collection.name),
+                    false,
+                );
+            }
+            if !output.failures.is_empty() {
+                let mut listing = String::new();
+                for failure in &output.failures {
+                    let line = format!("`{}`: {}\n", failure.entry, failure.reason);
+                    // Embed field values are capped at 1024 characters
+                    if listing.len() + line.len() > 1000 {
+                        listing.push_str("...");
+                        break;
+                    }
+                    listing.push_str(&line);
+                }
+                embed = embed.field(
+                    format!("Failed ({})", output.failures.len()),
+                    listing,
+                    false,
+                );
+            }
+            reply.embeds.insert(0, embed);
+            reply.content(String::new())
+        }
+        jobs::CANCELLED => CreateReply::default().content("Cancelled.").components(vec![]),
+        _ => CreateReply::default()
+            .content(format!(
+                "{} job failed: {}",
+                job.kind,
+                job.error.as_deref().unwrap_or("unknown error")
+            ))
+            .components(vec![]),
+    };
+    Ok(reply)
+}
+
+async fn load_job(data: &Data, job_id: i32) -> Result<db::Job, Error> {
+    let job = data
+        .db
+        .get()
+        .await?
+        .interact(move |x| {
+            use crate::schema::jobs::dsl::*;
+            use diesel::prelude::*;
+            jobs.find(job_id).select(db::Job::as_select()).first(x)
+        })
+        .await
+        .unwrap()?;
+    Ok(job)
+}
+
+async fn ensure_user(data: &Data, user: &serenity::User) -> Result<User, Error> {
+    let uid = user.id.get() as i64;
+    let username = user.name.clone();
+    let user = data
+        .db
+        .get()
+        .await?
+        .interact(move |x| User::get_or_create(uid, username, x))
+        .await
+        .unwrap()?;
+    Ok(user)
+}
+
+enum JobMessage<'a> {
+    Reply(Context<'a>, poise::ReplyHandle<'a>),
+    Followup(&'a serenity::Context, &'a serenity::ComponentInteraction, serenity::MessageId),
+}
+
+impl JobMessage<'_> {
+    async fn edit(&self, reply: CreateReply) -> Result<(), serenity::Error> {
+        match self {
+            JobMessage::Reply(ctx, handle) => handle.edit(*ctx, reply).await,
+            JobMessage::Followup(ctx, component, message_id) => component
+                .edit_followup(ctx, *message_id, reply_to_followup(reply))
+                .await
+                .map(|_| ()),
+        }
+    }
+}
+
+// Keeps the in-progress message up to date until the job finishes, then replaces it with the result
+async fn follow_job(
+    message: JobMessage<'_>,
+    data: &Data,
+    http: &serenity::Http,
+    watch: JobWatch,
+) -> Result<(), Error> {
+    let job_id = watch.id;
+    let progress = watch.progress.clone();
+    let message_ref = &message;
+    with_progress(watch.wait(), &progress, move |progress| async move {
+        let _ = message_ref
+            .edit(CreateReply::default().content(progress.to_string()))
+            .await;
+    })
+    .await;
+    let job = load_job(data, job_id).await?;
+    let reply = job_reply(data, &job).await?;
+    // Interaction tokens expire after 15 minutes, so long jobs have to report back by DM
+    if let Err(e) = message.edit(reply).await {
+        info!("Could not edit job {} message ({}), sending a DM instead", job_id, e);
+        jobs::notify_by_dm(data, http, &job).await?;
+    }
+    Ok(())
+}
+
+async fn run_job_in_reply(ctx: Context<'_>, spec: JobSpec) -> Result<(), Error> {
+    ensure_user(ctx.data(), ctx.author()).await?;
+    let watch = ctx
+        .data()
+        .jobs
+        .enqueue(&ctx.data().db, ctx.author().id.get(), spec)
+        .await?;
+    let handle = ctx
+        .send(
+            CreateReply::default()
+                .content("Queued")
+                .components(vec![cancel_button(watch.id)]),
+        )
+        .await?;
+    follow_job(
+        JobMessage::Reply(ctx, handle),
+        ctx.data(),
+        &ctx.serenity_context().http,
+        watch,
+    )
+    .await
+}
+
+async fn run_job_in_followup(
+    ctx: &serenity::Context,
+    component: &serenity::ComponentInteraction,
+    data: &Data,
+    spec: JobSpec,
+) -> Result<(), Error> {
+    component.defer(&ctx).await?;
+    ensure_user(data, &component.user).await?;
+    let watch = data
+        .jobs
+        .enqueue(&data.db, component.user.id.get(), spec)
+        .await?;
+    let message = component
+        .create_followup(
+            &ctx,
+            CreateInteractionResponseFollowup::new()
+                .content("Queued")
+                .components(vec![cancel_button(watch.id)]),
+        )
+        .await?;
+    follow_job(
+        JobMessage::Followup(ctx, component, message.id),
+        data,
+        &ctx.http,
+        watch,
+    )
+    .await
+}
+
+#[derive(poise::ChoiceParameter, Clone, Copy)]
+enum Expiry {
+    #[name = "1 day"]
+    Day,
+    #[name = "7 days"]
+    Week,
+    #[name = "30 days"]
+    Month,
+    #[name = "Forever"]
+    Forever,
+}
+
+impl Expiry {
+    fn seconds(&self) -> Option<i64> {
+        match self {
+            Expiry::Day => Some(60 * 60 * 24),
+            Expiry::Week => Some(60 * 60 * 24 * 7),
+            Expiry::Month => Some(60 * 60 * 24 * 30),
+            Expiry::Forever => None,
+        }
+    }
+}
+
+fn format_retention(seconds: i64) -> String {
+    format!("{} days", seconds / (60 * 60 * 24))
+}
+
+// Turns the requested expiry into a timestamp, or explains why the user's role doesn't allow it
+async fn resolve_expiry(data: &Data, user: User, expiry: Option<Expiry>) -> Result<Result<i64, String>, Error> {
+    let max_retention = data
+        .db
+        .get()
+        .await?
+        .interact(move |x| user.max_retention(x))
+        .await
+        .unwrap()?;
+    let Some(expiry) = expiry else {
+        let default = jobs::default_expiry_unix();
+        return Ok(Ok(match max_retention {
+            Some(max) => default.min(unix_now() + max),
+            None => default,
+        }));
+    };
+    Ok(match (expiry.seconds(), max_retention) {
+        (None, None) => Ok(db::PINNED),
+        (None, Some(max)) => Err(format!(
+            "Your role can't pin objects, they can be kept for at most {}",
+            format_retention(max)
+        )),
+        (Some(seconds), Some(max)) if seconds > max => Err(format!(
+            "Your role can keep objects for at most {}",
+            format_retention(max)
+        )),
+        (Some(seconds), _) => Ok(unix_now() + seconds),
+    })
+}
+
+// New expiry for an object that is extended by 7 days or pinned, within the role's retention limit
+fn extended_expiry(object: &Object, pin: bool, max_retention: Option<i64>) -> Result<i64, String> {
+    match (pin, max_retention) {
+        _ if object.expiry_unix == db::PINNED => Err("This object is already pinned".to_owned()),
+        (true, None) => Ok(db::PINNED),
+        (true, Some(max)) => Err(format!(
+            "Your role can't pin objects, they can be kept for at most {}",
+            format_retention(max)
+        )),
+        (false, max) => {
+            let extended = object.expiry_unix.max(unix_now()) + 60 * 60 * 24 * 7;
+            let limit = max.map(|max| object.created_unix + max);
+            match limit {
+                Some(limit) if object.expiry_unix >= limit => Err(format!(
+                    "This object can't be kept any longer, your role allows at most {}",
+                    format_retention(max.unwrap())
+                )),
+                Some(limit) => Ok(extended.min(limit)),
+                None => Ok(extended),
+            }
+        }
+    }
+}
+
+fn quota_exceeded_message(user: &User) -> String {
+    format!(
+        "Your storage quota is full ({} of {} used), delete some objects with /my_objects first",
+        humansize::format_size(user.bytes_used as u64, humansize::DECIMAL),
+        humansize::format_size(user.quota() as u64, humansize::DECIMAL)
+    )
+}
+
+#[poise::command(slash_command, install_context = "Guild|User", interaction_context = "Guild|BotDm|PrivateChannel")]
+async fn ytdlp(
+    ctx: Context<'_>,
+    #[description = "Video URL"] url: String,
+    #[description = "Archive every entry of a playlist or channel"] playlist: Option<bool>,
+    #[description = "Maximum number of playlist entries to download"] max_entries: Option<u32>,
+    #[description = "How long to keep the download"] expiry: Option<Expiry>,
+) -> Result<(), Error> {
+    ctx.defer().await?;
+    let user = ensure_user(ctx.data(), ctx.author()).await?;
+    if user.bytes_remaining() == 0 {
+        ctx.reply(quota_exceeded_message(&user)).await?;
+        return Ok(());
+    }
+    let expiry_unix = match resolve_expiry(ctx.data(), user, expiry).await? {
+        Ok(expiry_unix) => expiry_unix,
+        Err(message) => {
+            ctx.reply(message).await?;
+            return Ok(());
+        }
+    };
+    let playlist = playlist.unwrap_or(false);
+    if !playlist {
+        let uid = ctx.author().id.get() as i64;
+        let canonical_url = urls::canonicalize(&url);
+        let extractor_id = urls::extractor_id(&url);
+        let cached = ctx
+            .data()
+            .db
+            .get()
+            .await?
+            .interact(move |x| {
+                db::reuse_cached_object(
+                    uid,
+                    canonical_url,
+                    extractor_id,
+                    expiry_unix,
+                    x,
+                )
+            })
+            .await
+            .unwrap()?;
+        if let Some(object) = cached {
+            info!("Reusing object {} for {}", object.id, url);
+            ctx.send(embed_object(ctx.data(), object, ctx.author().id.get() as i64).await?)
+                .await?;
+            return Ok(());
+        }
+    }
+    let cap = YoutubeDownloader::playlist_entry_cap();
+    let spec = JobSpec::Download {
+        downloader: DownloaderKind::Ytdlp,
+        url,
+        playlist,
+        max_entries: max_entries.unwrap_or(cap).clamp(1, cap),
+        expiry_unix: Some(expiry_unix),
+    };
+    run_job_in_reply(ctx, spec).await
+}
+
+#[poise::command(slash_command, install_context = "Guild|User", interaction_context = "Guild|BotDm|PrivateChannel")]
+async fn gallerydl(
+    ctx: Context<'_>,
+    #[description = "Gallery or post URL"] url: String,
+    #[description = "How long to keep the download"] expiry: Option<Expiry>,
+) -> Result<(), Error> {
+    ctx.defer().await?;
+    let user = ensure_user(ctx.data(), ctx.author()).await?;
+    if user.bytes_remaining() == 0 {
+        ctx.reply(quota_exceeded_message(&user)).await?;
+        return Ok(());
+    }
+    let expiry_unix = match resolve_expiry(ctx.data(), user, expiry).await? {
+        Ok(expiry_unix) => expiry_unix,
+        Err(message) => {
+            ctx.reply(message).await?;
+            return Ok(());
+        }
+    };
+    let spec = JobSpec::Download {
+        downloader: DownloaderKind::GalleryDl,
+        url,
+        playlist: false,
+        max_entries: 0,
+        expiry_unix: Some(expiry_unix),
+    };
+    run_job_in_reply(ctx, spec).await
+}
+
+#[poise::command(slash_command, rename = "jobs", install_context = "Guild|User", interaction_context = "Guild|BotDm|PrivateChannel")]
+async fn list_jobs(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.defer().await?;
+    let uid = ctx.author().id.get() as i64;
+    let pending = ctx
+        .data()
+        .db
+        .get()
+        .await?
+        .interact(move |x| {
+            use crate::schema::jobs::dsl::*;
+            use diesel::prelude::*;
+            jobs.filter(user.eq(uid))
+                .filter(status.eq_any([crate::jobs::QUEUED, crate::jobs::RUNNING]))
+                .order_by(id.asc())
+                .select(db::Job::as_select())
+                .load(x)
+        })
+        .await
+        .unwrap()?;
+    if pending.is_empty() {
+        ctx.reply("You have no queued or running jobs").await?;
+        return Ok(());
+    }
+    let mut listing = String::new();
+    for job in pending {
+        let description = serde_json::from_str::<JobSpec>(&job.spec)
+            .map(|spec| spec.describe())
+            .unwrap_or_default();
+        let since = match job.status.as_str() {
+            crate::jobs::RUNNING => format!("started <t:{}:R>", job.updated_unix),
+            _ => format!("queued <t:{}:R>", job.created_unix),
+        };
+        listing.push_str(&format!(
+            "`[{}]` {} {} {}\n",
+            job.id, job.kind, description, since
+        ));
+    }
+    let embed = CreateEmbed::new()
+        .title("Your jobs")
+        .description(listing)
+        .color(serenity::Color::from_rgb(0, 0, 255));
+    ctx.send(CreateReply::default().embed(embed)).await?;
+    Ok(())
+}
+
+#[poise::command(slash_command, install_context = "Guild|User", interaction_context = "Guild|BotDm|PrivateChannel")]
+async fn my_objects(
+    ctx: Context<'_>,
+    #[description = "How to sort your objects"] sort: Option<ObjectSort>,
+) -> Result<(), Error> {
+    ctx.defer().await?;
+    let state = BrowserState {
+        user: ctx.author().id.get() as i64,
+        sort: sort.unwrap_or(ObjectSort::Expiry),
+        page: 0,
+    };
+    let (embed, components) = browser::render(ctx.data(), state).await?;
+    ctx.send(CreateReply::default().embed(embed).components(components))
+        .await?;
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+#[poise::command(slash_command, install_context = "Guild|User", interaction_context = "Guild|BotDm|PrivateChannel")]
+async fn search(
+    ctx: Context<'_>,
+    #[description = "Words in the name, uploader or description"] query: Option<String>,
+    #[description = "Source website, e.g. youtube.com"] domain: Option<String>,
+    #[description = "Kind of file"] media_type: Option<MediaType>,
+    #[description = "Minimum size in MB"] min_mb: Option<f64>,
+    #[description = "Maximum size in MB"] max_mb: Option<f64>,
+    #[description = "Archived on or after this date (YYYY-MM-DD)"] from: Option<String>,
+    #[description = "Archived on or before this date (YYYY-MM-DD)"] to: Option<String>,
+) -> Result<(), Error> {
+    ctx.defer().await?;
+    let mut dates = vec![];
+    for date in [&from, &to] {
+        match date.as_deref().map(search::parse_date) {
+            Some(None) => {
+                ctx.reply(format!(
+                    "`{}` is not a valid date, use YYYY-MM-DD",
+                    date.as_deref().unwrap()
+                ))
+                .await?;
+                return Ok(());
+            }
+            parsed => dates.push(parsed.flatten()),
+        }
+    }
+    let filters = SearchFilters {
+        user: ctx.author().id.get() as i64,
+        text: query,
+        domain,
+        media_type,
+        min_bytes: min_mb.map(|mb| (mb * 1_000_000.) as i64),
+        max_bytes: max_mb.map(|mb| (mb * 1_000_000.) as i64),
+        created_after: dates[0],
+        // Inclusive, so up to the end of that day
+        created_before: dates[1].map(|date| date + 60 * 60 * 24),
+    };
+    let results = ctx
+        .data()
+        .db
+        .get()
+        .await?
+        .interact(move |x| search::search(filters, x))
+        .await
+        .unwrap()?;
+    if results.is_empty() {
+        ctx.reply("No objects match your search").await?;
+        return Ok(());
+    }
+    let reply = embed_objects(ctx.data(), results, ctx.author().id.get() as i64).await?;
+    ctx.send(reply).await?;
+    Ok(())
+}
+
+async fn update_browser(
+    ctx: &serenity::Context,
+    component: &serenity::ComponentInteraction,
+    data: &Data,
+    state: BrowserState,
+) -> Result<(), Error> {
+    if state.user != component.user.id.get() as i64 {
+        component
+            .create_response(
+                &ctx,
+                serenity::CreateInteractionResponse::Message(
+                    serenity::CreateInteractionResponseMessage::new()
+                        .content("Use /my_objects to browse your own objects")
+                        .ephemeral(true),
+                ),
+            )
+            .await?;
+        return Ok(());
+    }
+    let (embed, components) = browser::render(data, state).await?;
+    component
+        .create_response(
+            &ctx,
+            serenity::CreateInteractionResponse::UpdateMessage(
+                serenity::CreateInteractionResponseMessage::new()
+                    .embed(embed)
+                    .components(components),
+            ),
+        )
+        .await?;
+    Ok(())
+}
+
+// Suggests the user's live objects for any command option that takes an object id, matching
+// the typed text against names and ids
+async fn autocomplete_object(ctx: Context<'_>, partial: &str) -> Vec<serenity::AutocompleteChoice> {
+    let uid = ctx.author().id.get() as i64;
+    let partial = partial.trim().to_owned();
+    let db = match ctx.data().db.get().await {
+        Ok(db) => db,
+        Err(e) => {
+            info!("Autocomplete could not get a connection: {}", e);
+            return vec![];
+        }
+    };
+    let matching = db
+        .interact(move |x| {
+            use crate::schema::objects::dsl::*;
+            use diesel::prelude::*;
+            let mut query = objects
+                .filter(user.eq(uid))
+                .filter(expiry_unix.gt(unix_now()))
+                .select(Object::as_select())
+                .into_boxed();
+            if !partial.is_empty() {
+                let pattern = format!("%{}%", partial.replace(['%', '_'], ""));
+                query = match partial.parse::<i32>() {
+                    Ok(partial_id) => query.filter(name.like(pattern).or(id.eq(partial_id))),
+                    Err(_) => query.filter(name.like(pattern)),
+                };
+            }
+            query.order_by(created_unix.desc()).limit(25).load(x)
+        })
+        .await
+        .unwrap();
+    let matching: Vec<Object> = match matching {
+        Ok(matching) => matching,
+        Err(e) => {
+            info!("Autocomplete query failed: {}", e);
+            return vec![];
+        }
+    };
+    matching
+        .into_iter()
+        .map(|object| {
+            let expires = if object.expiry_unix == db::PINNED {
+                "pinned".to_owned()
+            } else {
+                format!("{}d left", (object.expiry_unix - unix_now()) / (60 * 60 * 24))
+            };
+            let details = format!(
+                " ({}, {})",
+                humansize::format_size(object.size as u64, humansize::DECIMAL),
+                expires
+            );
+            // Choice names are capped at 100 characters
+            let name: String = object
+                .name
+                .chars()
+                .take(100 - details.chars().count())
+                .collect();
+            serenity::AutocompleteChoice::new(name + &details, object.id)
+        })
+        .collect()
+}
+
+#[poise::command(slash_command, install_context = "Guild|User", interaction_context = "Guild|BotDm|PrivateChannel")]
+async fn get_object(
+    ctx: Context<'_>,
+    #[description = "Object ID"]
+    #[autocomplete = "autocomplete_object"]
+    oid: i32,
+) -> Result<(), Error> {
+    let Some(object) = load_object(ctx, oid, Access::Read).await? else {
+        return Ok(());
+    };
+    ctx.defer().await?;
+    let create_reply = embed_object(ctx.data(), object, ctx.author().id.get() as i64).await?;
+    ctx.send(create_reply).await?;
+    Ok(())
+}
+
+async fn autocomplete_destination(ctx: Context<'_>, partial: &str) -> Vec<serenity::AutocompleteChoice> {
+    let uid = ctx.author().id.get() as i64;
+    let partial = partial.trim().to_lowercase();
+    let db = match ctx.data().db.get().await {
+        Ok(db) => db,
+        Err(e) => {
+            info!("Autocomplete could not get a connection: {}", e);
+            return vec![];
+        }
+    };
+    match db.interact(move |x| db::Destination::list(uid, x)).await.unwrap() {
+        Ok(destinations) => destinations
+            .into_iter()
+            .filter(|destination| destination.name.to_lowercase().contains(&partial))
+            .take(25)
+            .map(|destination| {
+                let label = match destination.is_default {
+                    true => format!("{} (default)", destination.name),
+                    false => destination.name,
+                };
+                serenity::AutocompleteChoice::new(label, destination.id)
+            })
+            .collect(),
+        Err(e) => {
+            info!("Autocomplete query failed: {}", e);
+            vec![]
+        }
+    }
+}
+
+// Loads one of the author's destinations, replying with an error if there is none
+async fn load_destination(ctx: Context<'_>, destination_id: i32) -> Result<Option<db::Destination>, Error> {
+    let uid = ctx.author().id.get() as i64;
+    let destination = ctx
+        .data()
+        .db
+        .get()
+        .await?
+        .interact(move |x| db::Destination::find(uid, Some(destination_id), x))
+        .await
+        .unwrap()?;
+    if destination.is_none() {
+        ctx.send(
+            CreateReply::default()
+                .content("You don't have a destination with that id")
+                .ephemeral(true),
+        )
+        .await?;
+    }
+    Ok(destination)
+}
+
+// Destination configs contain upload tokens, so everything about them is only shown to their owner
+#[poise::command(slash_command, subcommands("destination_add", "destination_list", "destination_remove", "destination_default"), subcommand_required, install_context = "Guild|User", interaction_context = "Guild|BotDm|PrivateChannel")]
+async fn destination(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+const MAX_UPLOADER_CONFIG_SIZE: u32 = 64 * 1024;
+
+// Only names are shown for arguments and headers, their values are usually tokens
+fn uploader_summary(uploader: &sharex::CustomUploader, name: &str) -> CreateEmbed {
+    let host = url::Url::parse(&uploader.request_url.replace("{filename}", "file"))
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_owned))
+        .unwrap_or_default();
+    let keys = |map: &HashMap<String, String>| {
+        let mut keys = map.keys().map(|key| format!("`{key}`")).collect::<Vec<_>>();
+        keys.sort();
+        if keys.is_empty() {
+            "None".to_owned()
+        } else {
+            keys.join(", ")
+        }
+    };
+    let template = |template: &Option<String>| match template.as_deref() {
+        Some(template) if !template.trim().is_empty() => format!("`{}`", template.replace('`', "'")),
+        _ => "None".to_owned(),
+    };
+    let mut embed = CreateEmbed::new()
+        .title(name)
+        .field("Request", format!("{} {}", uploader.method(), host), true)
+        .field("Body", format!("{:?}", uploader.body_type()), true)
+        .field(
+            "File field",
+            uploader.file_form_name.clone().unwrap_or("None".to_owned()),
+            true,
+        )
+        .field("Arguments", keys(&uploader.arguments), false)
+        .field("Headers", keys(&uploader.headers), false)
+        .field("URL", template(&uploader.url), false)
+        .field("Thumbnail URL", template(&uploader.thumbnail_url), false)
+        .field("Deletion URL", template(&uploader.deletion_url), false);
+    let warnings = uploader.warnings();
+    if !warnings.is_empty() {
+        embed = embed.field("Warnings", warnings.join("\n"), false);
+    }
+    embed
+}
+
+// Uploads a tiny image so problems with the endpoint show up now instead of on the first real upload
+// upload refuses non-public addresses before anything is sent, so this can't probe the bot's network
+async fn test_upload(uploader: &sharex::CustomUploader) -> anyhow::Result<sharex::UploadResult> {
+    let path = storage::staging_file("png")?;
+    tokio::fs::write(&path, sharex::TEST_IMAGE).await?;
+    let result = uploader.upload(&path, "archivebot-test.png").await;
+    path.close()?;
+    result
+}
+
+#[poise::command(slash_command, rename = "add")]
+async fn destination_add(
+    ctx: Context<'_>,
+    #[description = "A ShareX custom uploader (.sxcu) file"] file: Option<serenity::Attachment>,
+    #[description = "Contents of a ShareX custom uploader, instead of a file"] json_text: Option<String>,
+    #[description = "Name to show in menus, defaults to the uploader's own name"]
+    #[max_length = 80]
+    name: Option<String>,
+    #[description = "Upload a small test image to check the uploader works before saving it"] dry_run: Option<bool>,
+) -> Result<(), Error> {
+    ctx.defer_ephemeral().await?;
+    let json_text = match (file, json_text) {
+        (Some(file), _) => {
+            let extension = std::path::Path::new(&file.filename)
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .map(str::to_lowercase);
+            if !matches!(extension.as_deref(), Some("sxcu" | "json")) {
+                ctx.reply("Attach a .sxcu or .json file").await?;
+                return Ok(());
+            }
+            if file.size > MAX_UPLOADER_CONFIG_SIZE {
+                ctx.reply("That file is too big to be a ShareX custom uploader")
+                    .await?;
+                return Ok(());
+            }
+            match String::from_utf8(file.download().await?) {
+                Ok(text) => text,
+                Err(_) => {
+                    ctx.reply("That file is not text").await?;
+                    return Ok(());
+                }
+            }
+        }
+        (None, Some(json_text)) => json_text,
+        (None, None) => {
+            ctx.reply("Attach a .sxcu file or paste its contents into json_text")
+                .await?;
+            return Ok(());
+        }
+    };
+    // ShareX writes .sxcu files with a byte order mark
+    let json_text = json_text.trim_start_matches('\u{feff}').to_owned();
+    let uploader = match sharex::CustomUploader::from_json(&json_text)
+        .and_then(|uploader| uploader.validate().map(|_| uploader))
+    {
+        Ok(uploader) => uploader,
+        Err(e) => {
+            ctx.reply(format!("That is not a valid ShareX custom uploader: {e}"))
+                .await?;
+            return Ok(());
+        }
+    };
+    let name = name
+        .or(uploader.name.clone())
+        .map(|name| name.trim().to_owned())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| {
+            url::Url::parse(&uploader.request_url)
+                .ok()
+                .and_then(|url| url.host_str().map(str::to_owned))
+                .unwrap_or("Uploader".to_owned())
+        });
+    let mut summary = uploader_summary(&uploader, &name);
+    if dry_run.unwrap_or(false) {
+        match test_upload(&uploader).await {
+            Ok(result) => {
+                summary = summary.field("Test upload", result.url, false);
+                let cleanup = match result.deletion_url.filter(|url| !url.is_empty()) {
+                    Some(deletion_url) => match sharex::delete_remote(&deletion_url).await {
+                        Ok(()) => "Deleted from the host".to_owned(),
+                        Err(e) => format!("Couldn't delete it ({e}), use {deletion_url} instead"),
+                    },
+                    None => "The uploader has no DeletionURL, so the test file was left on the host"
+                        .to_owned(),
+                };
+                summary = summary.field("Test file", cleanup, false);
+            }
+            Err(e) => {
+                ctx.send(
+                    CreateReply::default()
+                        .content(format!("The test upload failed, so {name} wasn't added: {e}"))
+                        .embed(summary),
+                )
+                .await?;
+                return Ok(());
+            }
+        }
+    }
+    let stored = sharex::seal(&json_text, &ctx.data().credentials)?;
+    let user = ensure_user(ctx.data(), ctx.author()).await?;
+    let uid = user.snowflake;
+    let destination_name = name.clone();
+    let added = ctx
+        .data()
+        .db
+        .get()
+        .await?
+        .interact(move |x| {
+            use crate::schema::destinations::dsl::*;
+            use diesel::prelude::*;
+            x.transaction(|x| {
+                let taken = destinations
+                    .filter(user.eq(uid))
+                    .filter(name.eq(&destination_name))
+                    .count()
+                    .get_result::<i64>(x)?;
+                if taken > 0 {
+                    return Ok(None);
+                }
+                // The first destination is the default
+                let first = destinations
+                    .filter(user.eq(uid))
+                    .count()
+                    .get_result::<i64>(x)?
+                    == 0;
+                diesel::insert_into(destinations)
+                    .values(db::NewDestination {
+                        user: uid,
+                        name: destination_name,
+                        json: stored,
+                        is_default: first,
+                        created_unix: unix_now(),
+                    })
+                    .returning(db::Destination::as_returning())
+                    .get_result(x)
+                    .map(Some)
+            })
+        })
+        .await
+        .unwrap()?;
+    let message = match added {
+        None => format!("You already have a destination called {name}, remove it first"),
+        Some(destination) if destination.is_default => {
+            format!("Added {name}, it's your default destination")
+        }
+        Some(_) => format!("Added {name}, use /destination default to upload there by default"),
+    };
+    ctx.send(CreateReply::default().content(message).embed(summary))
+        .await?;
+    Ok(())
+}
+
+#[poise::command(slash_command, rename = "list")]
+async fn destination_list(ctx: Context<'_>) -> Result<(), Error> {
+    let uid = ctx.author().id.get() as i64;
+    let destinations = ctx
+        .data()
+        .db
+        .get()
+        .await?
+        .interact(move |x| db::Destination::list(uid, x))
+        .await
+        .unwrap()?;
+    let mut listing = String::new();
+    for destination in &destinations {
+        let host = sharex::CustomUploader::from_stored(&destination.json, &ctx.data().credentials)
+            .ok()
+            .and_then(|uploader| url::Url::parse(&uploader.request_url).ok())
+            .and_then(|url| url.host_str().map(str::to_owned))
+            .unwrap_or("invalid config".to_owned());
+        listing.push_str(&format!(
+            "`[{}]` {} ({}, added <t:{}:R>){}\n",
+            destination.id,
+            destination.name,
+            host,
+            destination.created_unix,
+            if destination.is_default { " **default**" } else { "" }
+        ));
+    }
+    if listing.is_empty() {
+        listing = "You don't have any destinations yet, add one with /destination add".to_owned();
+    } else if destinations.len() > MAX_PICKER_DESTINATIONS {
+        listing.push_str(&format!(
+            "Only the first {MAX_PICKER_DESTINATIONS} can be picked from object menus"
+        ));
+    }
+    let embed = CreateEmbed::new()
+        .title("Your upload destinations")
+        .description(listing)
+        .color(serenity::Color::from_rgb(0, 0, 255));
+    ctx.send(CreateReply::default().embed(embed).ephemeral(true))
+        .await?;
+    Ok(())
+}
+
+#[poise::command(slash_command, rename = "remove")]
+async fn destination_remove(
+    ctx: Context<'_>,
+    #[description = "Destination"]
+    #[autocomplete = "autocomplete_destination"]
+    destination: i32,
+) -> Result<(), Error> {
+    let Some(destination) = load_destination(ctx, destination).await? else {
+        return Ok(());
+    };
+    let name = destination.name.clone();
+    let new_default = ctx
+        .data()
+        .db
+        .get()
+        .await?
+        .interact(move |x| destination.delete(x))
+        .await
+        .unwrap()?;
+    let message = match new_default {
+        Some(new_default) => format!("Removed {name}, {} is now your default", new_default.name),
+        None => format!("Removed {name}"),
+    };
+    ctx.send(CreateReply::default().content(message).ephemeral(true))
+        .await?;
+    Ok(())
+}
+
+#[poise::command(slash_command, rename = "default")]
+async fn destination_default(
+    ctx: Context<'_>,
+    #[description = "Destination"]
+    #[autocomplete = "autocomplete_destination"]
+    destination: i32,
+) -> Result<(), Error> {
+    let Some(destination) = load_destination(ctx, destination).await? else {
+        return Ok(());
+    };
+    let name = destination.name.clone();
+    ctx.data()
+        .db
+        .get()
+        .await?
+        .interact(move |x| destination.set_default(x))
+        .await
+        .unwrap()?;
+    ctx.send(
+        CreateReply::default()
+            .content(format!("{name} is now your default destination"))
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+#[poise::command(slash_command, install_context = "Guild|User", interaction_context = "Guild|BotDm|PrivateChannel")]
+async fn usage(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.defer().await?;
+    let user = ensure_user(ctx.data(), ctx.author()).await?;
+    let uid = user.snowflake;
+    let (largest, expiring, pinned) = ctx
+        .data()
+        .db
+        .get()
+        .await?
+        .interact(move |x| {
+            use crate::schema::objects::dsl::*;
+            use diesel::prelude::*;
+            let largest = objects
+                .filter(user.eq(uid))
+                .order_by(size.desc())
+                .limit(5)
+                .select(Object::as_select())
+                .load(x)?;
+            let expiring = objects
+                .filter(user.eq(uid))
+                .filter(expiry_unix.ne(db::PINNED))
+                .order_by(expiry_unix.asc())
+                .limit(5)
+                .select(Object::as_select())
+                .load(x)?;
+            let pinned = objects
+                .filter(user.eq(uid))
+                .filter(expiry_unix.eq(db::PINNED))
+                .select(size)
+                .load::<i64>(x)?;
+            Ok::<_, diesel::result::Error>((largest, expiring, pinned))
+        })
+        .await
+        .unwrap()?;
+    let format_size = |bytes: i64| humansize::format_size(bytes.max(0) as u64, humansize::DECIMAL);
+    let percent = user.bytes_used as f64 / user.quota().max(1) as f64 * 100.;
+    let mut largest_listing = String::new();
+    for object in &largest {
+        largest_listing.push_str(&format!(
+            "`[{}]` {} ({})\n",
+            object.id,
+            object.name.chars().take(60).collect::<String>(),
+            format_size(object.size)
+        ));
+    }
+    let mut expiring_listing = String::new();
+    for object in &expiring {
+        expiring_listing.push_str(&format!(
+            "`[{}]` {} <t:{}:R>\n",
+            object.id,
+            object.name.chars().take(60).collect::<String>(),
+            object.expiry_unix
+        ));
+    }
+    let mut embed = CreateEmbed::new()
+        .title("Storage usage")
+        .description(format!(
+            "{} of {} used ({percent:.1}%), {} left",
+            format_size(user.bytes_used),
+            format_size(user.quota()),
+            format_size(user.bytes_remaining())
+        ))
+        .color(serenity::Color::from_rgb(0, 0, 255))
+        .field("Objects", user.object_count.to_string(), true)
+        .field(
+            "Pinned",
+            format!("{} ({})", pinned.len(), format_size(pinned.iter().sum())),
+            true,
+        );
+    if !largest_listing.is_empty() {
+        embed = embed.field("Largest objects", largest_listing, false);
+    }
+    if !expiring_listing.is_empty() {
+        embed = embed.field("Expiring next", expiring_listing, false);
+    }
+    ctx.send(CreateReply::default().embed(embed)).await?;
+    Ok(())
+}
+
+// Removes the objects and whichever of their files nothing else references anymore
+async fn delete_objects(data: &Data, object_ids: Vec<i32>) -> Result<(), Error> {
+    let released = data
+        .db
+        .get()
+        .await?
+        .interact(move |x| {
+            let mut released = vec![];
+            for oid in object_ids {
+                released.extend(db::delete_object(oid, x)?);
+            }
+            Ok::<_, Error>(released)
+        })
+        .await
+        .unwrap()?;
+    // Other objects may still point at the same blob
+    for path in released {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+async fn autocomplete_collection(ctx: Context<'_>, partial: &str) -> Vec<serenity::AutocompleteChoice> {
+    let uid = ctx.author().id.get() as i64;
+    let pattern = format!("%{}%", partial.trim().replace(['%', '_'], ""));
+    let db = match ctx.data().db.get().await {
+        Ok(db) => db,
+        Err(e) => {
+            info!("Autocomplete could not get a connection: {}", e);
+            return vec![];
+        }
+    };
+    let matching = db
+        .interact(move |x| {
+            use crate::schema::collections::dsl::*;
+            use diesel::prelude::*;
+            let shared = crate::schema::collection_shares::table
+                .filter(crate::schema::collection_shares::user.eq(uid))
+                .select(crate::schema::collection_shares::collection_id);
+            collections
+                .filter(user.eq(uid).or(id.eq_any(shared)))
+                .filter(name.like(pattern))
+                .order_by(id.desc())
+                .limit(25)
+                .select(db::Collection::as_select())
+                .load(x)
+        })
+        .await
+        .unwrap();
+    match matching {
+        Ok(matching) => matching
+            .into_iter()
+            .map(|collection| {
+                let name: String = collection.name.chars().take(100).collect();
+                serenity::AutocompleteChoice::new(name, collection.id)
+            })
+            .collect(),
+        Err(e) => {
+            info!("Autocomplete query failed: {}", e);
+            vec![]
+        }
+    }
+}
+
+// Loads a collection with its objects if the author has the required access to it, otherwise
+// replies with why they can't use it
+async fn load_collection(
+    ctx: Context<'_>,
+    collection_id: i32,
+    required: Access,
+) -> Result<Option<(db::Collection, Vec<Object>)>, Error> {
+    let uid = ctx.author().id.get() as i64;
+    let (access, collection) = ctx
+        .data()
+        .db
+        .get()
+        .await?
+        .interact(move |x| {
+            use diesel::prelude::*;
+            let access = sharing::collection_access(collection_id, uid, x)?;
+            if access.is_none_or(|access| access < required) {
+                return Ok((access, None));
+            }
+            let collection = crate::schema::collections::table
+                .find(collection_id)
+                .select(db::Collection::as_select())
+                .first(x)?;
+            let objects = collection.objects(x)?;
+            Ok::<_, diesel::result::Error>((access, Some((collection, objects))))
+        })
+        .await
+        .unwrap()?;
+    if collection.is_none() {
+        let message = match access {
+            Some(_) => required.denied_message("collection"),
+            None => "You don't have a collection with that id".to_owned(),
+        };
+        ctx.send(CreateReply::default().content(message).ephemeral(true))
+            .await?;
+    }
+    Ok(collection)
+}
+
+// Same as load_collection, for commands that work on a single object
+async fn load_object(ctx: Context<'_>, oid: i32, required: Access) -> Result<Option<Object>, Error> {
+    let uid = ctx.author().id.get() as i64;
+    let (access, object) = ctx
+        .data()
+        .db
+        .get()
+        .await?
+        .interact(move |x| {
+            use crate::schema::objects::dsl::*;
+            use diesel::prelude::*;
+            let access = sharing::object_access(oid, uid, x)?;
+            if access.is_none_or(|access| access < required) {
+                return Ok((access, None));
+            }
+            let object = objects.find(oid).select(Object::as_select()).first(x)?;
+            Ok::<_, diesel::result::Error>((access, Some(object)))
+        })
+        .await
+        .unwrap()?;
+    if object.is_none() {
+        let message = match access {
+            Some(_) => required.denied_message("object"),
+            None => "You don't have an object with that id".to_owned(),
+        };
+        ctx.send(CreateReply::default().content(message).ephemeral(true))
+            .await?;
+    }
+    Ok(object)
+}
+
+#[poise::command(slash_command, subcommands("collection_create", "collection_list", "collection_show", "collection_rename", "collection_add", "collection_remove", "collection_extend", "collection_delete", "collection_upload"), subcommand_required, install_context = "Guild|User", interaction_context = "Guild|BotDm|PrivateChannel")]
+async fn collection(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+#[poise::command(slash_command, rename = "create")]
+async fn collection_create(
+    ctx: Context<'_>,
+    #[description = "Collection name"]
+    #[max_length = 100]
+    name: String,
+) -> Result<(), Error> {
+    ensure_user(ctx.data(), ctx.author()).await?;
+    let new_collection = db::NewCollection {
+        user: ctx.author().id.get() as i64,
+        name: name.trim().to_owned(),
+        source_url: None,
+    };
+    let collection = ctx
+        .data()
+        .db
+        .get()
+        .await?
+        .interact(move |x| new_collection.create_with_objects(vec![], x))
+        .await
+        .unwrap()?;
+    ctx.reply(format!(
+        "Created collection `[{}]` {}",
+        collection.id, collection.name
+    ))
+    .await?;
+    Ok(())
+}
+
+#[poise::command(slash_command, rename = "list")]
+async fn collection_list(ctx: Context<'_>) -> Result<(), Error> {
+    let uid = ctx.author().id.get() as i64;
+    let collections = ctx
+        .data()
+        .db
+        .get()
+        .await?
+        .interact(move |x| {
+            use crate::schema::{collection_objects, collections, objects};
+            use diesel::prelude::*;
+            let owned = collections::table
+                .filter(collections::user.eq(uid))
+                .order_by(collections::id.desc())
+                .select(db::Collection::as_select())
+                .load(x)?;
+            let sizes = collection_objects::table
+                .inner_join(objects::table)
+                .filter(collection_objects::collection_id.eq_any(owned.iter().map(|c| c.id)))
+                .select((collection_objects::collection_id, objects::size))
+                .load::<(i32, i64)>(x)?;
+            let mut totals = HashMap::<i32, (usize, i64)>::new();
+            for (collection_id, size) in sizes {
+                let total = totals.entry(collection_id).or_default();
+                total.0 += 1;
+                total.1 += size;
+            }
+            Ok::<_, diesel::result::Error>(
+                owned
+                    .into_iter()
+                    .map(|collection| {
+                        let total = totals.get(&collection.id).copied().unwrap_or_default();
+                        (collection, total)
+                    })
+                    .collect::<Vec<_>>(),
+            )
+        })
+        .await
+        .unwrap()?;
+    let mut listing = String::new();
+    for (collection, (count, size)) in &collections {
+        let line = format!(
+            "`[{}]` {} ({} objects, {})\n",
+            collection.id,
+            collection.name,
+            count,
+            humansize::format_size(*size as u64, humansize::DECIMAL)
+        );
+        // Embed descriptions are capped at 4096 characters
+        if listing.len() + line.len() > 4000 {
+            listing.push_str("...");
+            break;
+        }
+        listing.push_str(&line);
+    }
+    if listing.is_empty() {
+        listing = "You don't have any collections yet, create one with /collection create".to_owned();
+    }
+    let embed = CreateEmbed::new()
+        .title(format!("Your collections ({})", collections.len()))
+        .description(listing)
+        .color(serenity::Color::from_rgb(0, 0, 255));
+    ctx.send(CreateReply::default().embed(embed)).await?;
+    Ok(())
+}
+
+#[poise::command(slash_command, rename = "show")]
+async fn collection_show(
+    ctx: Context<'_>,
+    #[description = "Collection"]
+    #[autocomplete = "autocomplete_collection"]
+    collection: i32,
+) -> Result<(), Error> {
+    let Some((collection, objects)) = load_collection(ctx, collection, Access::Read).await? else {
+        return Ok(());
+    };
+    if objects.is_empty() {
+        ctx.reply(format!(
+            "{} is empty, add objects with /collection add",
+            collection.name
+        ))
+        .await?;
+        return Ok(());
+    }
+    let mut reply = embed_objects(ctx.data(), objects, ctx.author().id.get() as i64).await?;
+    let embed = reply.embeds.remove(0).field(
+        "Collection",
+        format!("`[{}]` {}", collection.id, collection.name),
+        false,
+    );
+    reply.embeds.insert(0, embed);
+    ctx.send(reply).await?;
+    Ok(())
+}
+
+#[poise::command(slash_command, rename = "rename")]
+async fn collection_rename(
+    ctx: Context<'_>,
+    #[description = "Collection"]
+    #[autocomplete = "autocomplete_collection"]
+    collection: i32,
+    #[description = "New name"]
+    #[max_length = 100]
+    name: String,
+) -> Result<(), Error> {
+    let Some((collection, _)) = load_collection(ctx, collection, Access::Owner).await? else {
+        return Ok(());
+    };
+    let new_name = name.trim().to_owned();
+    let collection_id = collection.id;
+    let renamed = ctx
+        .data()
+        .db
+        .get()
+        .await?
+        .interact(move |x| {
+            use crate::schema::collections::dsl::*;
+            use diesel::prelude::*;
+            diesel::update(collections.find(collection_id))
+                .set(name.eq(new_name))
+                .returning(db::Collection::as_returning())
+                .get_result(x)
+        })
+        .await
+        .unwrap()?;
+    ctx.reply(format!("Renamed {} to {}", collection.name, renamed.name))
+        .await?;
+    Ok(())
+}
+
+#[poise::command(slash_command, rename = "add")]
+async fn collection_add(
+    ctx: Context<'_>,
+    #[description = "Collection"]
+    #[autocomplete = "autocomplete_collection"]
+    collection: i32,
+    #[description = "Object to add"]
+    #[autocomplete = "autocomplete_object"]
+    object: i32,
+) -> Result<(), Error> {
+    let Some((collection, _)) = load_collection(ctx, collection, Access::Owner).await? else {
+        return Ok(());
+    };
+    let Some(object) = load_object(ctx, object, Access::Owner).await? else {
+        return Ok(());
+    };
+    let oid = object.id;
+    let to_update = collection.clone();
+    let added = ctx
+        .data()
+        .db
+        .get()
+        .await?
+        .interact(move |x| to_update.add_objects(&[oid], x))
+        .await
+        .unwrap()?;
+    let message = match added {
+        0 => format!("{} is already in {}", object.name, collection.name),
+        _ => format!("Added {} to {}", object.name, collection.name),
+    };
+    ctx.reply(message).await?;
+    Ok(())
+}
+
+#[poise::command(slash_command, rename = "remove")]
+async fn collection_remove(
+    ctx: Context<'_>,
+    #[description = "Collection"]
+    #[autocomplete = "autocomplete_collection"]
+    collection: i32,
+    #[description = "Object to remove, it is not deleted"]
+    #[autocomplete = "autocomplete_object"]
+    object: i32,
+) -> Result<(), Error> {
+    let Some((collection, _)) = load_collection(ctx, collection, Access::Manage).await? else {
+        return Ok(());
+    };
+    let to_update = collection.clone();
+    let removed = ctx
+        .data()
+        .db
+        .get()
+        .await?
+        .interact(move |x| to_update.remove_object(object, x))
+        .await
+        .unwrap()?;
+    let message = match removed {
+        0 => format!("Object {} isn't in {}", object, collection.name),
+        _ => format!("Removed object {} from {}", object, collection.name),
+    };
+    ctx.reply(message).await?;
+    Ok(())
+}
+
+#[poise::command(slash_command, rename = "extend")]
+async fn collection_extend(
+    ctx: Context<'_>,
+    #[description = "Collection"]
+    #[autocomplete = "autocomplete_collection"]
+    collection: i32,
+    #[description = "Pin the objects forever instead of extending them by 7 days"] pin: Option<bool>,
+) -> Result<(), Error> {
+    let Some((collection, objects)) = load_collection(ctx, collection, Access::Manage).await? else {
+        return Ok(());
+    };
+    ctx.defer().await?;
+    // Retention limits follow the owner's role, not the role of whoever manages the collection
+    let owner = collection.user;
+    let max_retention = ctx
+        .data()
+        .db
+        .get()
+        .await?
+        .interact(move |x| User::get(owner, x)?.max_retention(x).map_err(Error::from))
+        .await
+        .unwrap()?;
+    let mut updates = vec![];
+    let mut skipped = HashMap::<String, usize>::new();
+    for object in &objects {
+        match extended_expiry(object, pin.unwrap_or(false), max_retention) {
+            Ok(new_expiry) => updates.push((object.id, new_expiry)),
+            Err(reason) => *skipped.entry(reason).or_default() += 1,
+        }
+    }
+    let extended = updates.len();
+    ctx.data()
+        .db
+        .get()
+        .await?
+        .interact(move |x| {
+            use crate::schema::objects::dsl::*;
+            use diesel::prelude::*;
+            x.transaction(|x| {
+                for (oid, new_expiry) in updates {
+                    diesel::update(objects.find(oid))
+                        .set(expiry_unix.eq(new_expiry))
+                        .execute(x)?;
+                }
+                Ok::<_, diesel::result::Error>(())
+            })
+        })
+        .await
+        .unwrap()?;
+    let mut message = format!(
+        "{} {} of {} objects in {}",
+        if pin.unwrap_or(false) { "Pinned" } else { "Extended" },
+        extended,
+        objects.len(),
+        collection.name
+    );
+    for (reason, count) in skipped {
+        message.push_str(&format!("\n{count} skipped: {reason}"));
+    }
+    ctx.reply(message).await?;
+    Ok(())
+}
+
+#[poise::command(slash_command, rename = "delete")]
+async fn collection_delete(
+    ctx: Context<'_>,
+    #[description = "Collection"]
+    #[autocomplete = "autocomplete_collection"]
+    collection: i32,
+    #[description = "Also permanently delete every object in the collection"] with_objects: Option<bool>,
+) -> Result<(), Error> {
+    let Some((collection, objects)) = load_collection(ctx, collection, Access::Owner).await? else {
+        return Ok(());
+    };
+    ctx.defer().await?;
+    // Objects are kept unless asked for, deleting them can't be undone
+    let keep_objects = !with_objects.unwrap_or(false);
+    let name = collection.name.clone();
+    ctx.data()
+        .db
+        .get()
+        .await?
+        .interact(move |x| collection.delete(x))
+        .await
+        .unwrap()?;
+    if keep_objects {
+        ctx.reply(format!("Deleted {name}, its {} objects were kept", objects.len()))
+            .await?;
+        return Ok(());
+    }
+    let count = objects.len();
+    delete_objects(ctx.data(), objects.iter().map(|object| object.id).collect()).await?;
+    ctx.reply(format!("Deleted {name} and its {count} objects"))
+        .await?;
+    Ok(())
+}
+
+// Bots can send at most 10 attachments and 10MB per message
+const DISCORD_MAX_ATTACHMENTS: usize = 10;
+const DISCORD_MAX_UPLOAD: i64 = 10_000_000;
+
+#[poise::command(slash_command, rename = "upload")]
+async fn collection_upload(
+    ctx: Context<'_>,
+    #[description = "Collection"]
+    #[autocomplete = "autocomplete_collection"]
+    collection: i32,
+) -> Result<(), Error> {
+    let Some((collection, objects)) = load_collection(ctx, collection, Access::Read).await? else {
+        return Ok(());
+    };
+    ctx.defer().await?;
+    let (uploadable, too_large): (Vec<_>, Vec<_>) = objects
+        .into_iter()
+        .partition(|object| object.size <= DISCORD_MAX_UPLOAD);
+    let mut batches: Vec<Vec<Object>> = vec![];
+    for object in uploadable {
+        let fits = batches.last().is_some_and(|batch| {
+            batch.len() < DISCORD_MAX_ATTACHMENTS
+                && batch.iter().map(|object| object.size).sum::<i64>() + object.size
+                    <= DISCORD_MAX_UPLOAD
+        });
+        match batches.last_mut() {
+            Some(batch) if fits => batch.push(object),
+            _ => batches.push(vec![object]),
+        }
+    }
+    for batch in batches {
+        let mut reply = CreateReply::default();
+        for object in batch {
+            reply = reply.attachment(
+                CreateAttachment::path(&object.path)
+                    .await?
+                    .description(object.name),
+            );
+        }
+        ctx.send(reply).await?;
+    }
+    let mut message = format!("Uploaded {}", collection.name);
+    if !too_large.is_empty() {
+        let names = too_large
+            .iter()
+            .map(|object| format!("`[{}]` {}", object.id, object.name))
+            .collect::<Vec<_>>();
+        message.push_str(&format!(
+            "\nThese objects are too large for discord, compress them first:\n{}",
+            names.join("\n")
+        ));
+    }
+    ctx.reply(message).await?;
+    Ok(())
+}
+
+#[poise::command(slash_command, subcommands("tag_add", "tag_remove", "tag_list"), subcommand_required, install_context = "Guild|User", interaction_context = "Guild|BotDm|PrivateChannel")]
+async fn tag(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+#[poise::command(slash_command, rename = "add")]
+async fn tag_add(
+    ctx: Context<'_>,
+    #[description = "Object to tag"]
+    #[autocomplete = "autocomplete_object"]
+    object: i32,
+    #[description = "Tag"] tag: String,
+) -> Result<(), Error> {
+    let Some(normalized) = db::normalize_tag(&tag) else {
+        ctx.send(
+            CreateReply::default()
+                .content(format!("Tags have to be 1 to {} characters long", db::MAX_TAG_LENGTH))
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    };
+    let Some(object) = load_object(ctx, object, Access::Manage).await? else {
+        return Ok(());
+    };
+    let new_tag = db::ObjectTag {
+        object_id: object.id,
+        tag: normalized.clone(),
+    };
+    ctx.data()
+        .db
+        .get()
+        .await?
+        .interact(move |x| {
+            use diesel::prelude::*;
+            diesel::insert_or_ignore_into(crate::schema::object_tags::table)
+                .values(&new_tag)
+                .execute(x)
+        })
+        .await
+        .unwrap()?;
+    ctx.reply(format!("Tagged {} with `{}`", object.name, normalized))
+        .await?;
+    Ok(())
+}
+
+#[poise::command(slash_command, rename = "remove")]
+async fn tag_remove(
+    ctx: Context<'_>,
+    #[description = "Object to untag"]
+    #[autocomplete = "autocomplete_object"]
+    object: i32,
+    #[description = "Tag"] tag: String,
+) -> Result<(), Error> {
+    let Some(object) = load_object(ctx, object, Access::Manage).await? else {
+        return Ok(());
+    };
+    let normalized = db::normalize_tag(&tag).unwrap_or_default();
+    let oid = object.id;
+    let to_remove = normalized.clone();
+    let removed = ctx
+        .data()
+        .db
+        .get()
+        .await?
+        .interact(move |x| {
+            use crate::schema::object_tags::dsl::*;
+            use diesel::prelude::*;
+            diesel::delete(object_tags.find((oid, to_remove))).execute(x)
+        })
+        .await
+        .unwrap()?;
+    let message = match removed {
+        0 => format!("{} isn't tagged with `{}`", object.name, normalized),
+        _ => format!("Removed `{}` from {}", normalized, object.name),
+    };
+    ctx.reply(message).await?;
+    Ok(())
+}
+
+#[poise::command(slash_command, rename = "list")]
+async fn tag_list(
+    ctx: Context<'_>,
+    #[description = "Show the objects with this tag instead of all your tags"] tag: Option<String>,
+) -> Result<(), Error> {
+    let uid = ctx.author().id.get() as i64;
+    let Some(tag) = tag else {
+        let tags = ctx
+            .data()
+            .db
+            .get()
+            .await?
+            .interact(move |x| {
+                use crate::schema::{object_tags, objects};
+                use diesel::prelude::*;
+                object_tags::table
+                    .inner_join(objects::table)
+                    .filter(objects::user.eq(uid))
+                    .group_by(object_tags::tag)
+                    .order_by(object_tags::tag.asc())
+                    .select((object_tags::tag, diesel::dsl::count_star()))
+                    .load::<(String, i64)>(x)
+            })
+            .await
+            .unwrap()?;
+        let listing = if tags.is_empty() {
+            "You haven't tagged any objects yet, tag them with /tag add".to_owned()
+        } else {
+            tags.iter()
+                .map(|(tag, count)| format!("`{tag}` ({count})"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let embed = CreateEmbed::new()
+            .title("Your tags")
+            .description(listing.chars().take(4000).collect::<String>())
+            .color(serenity::Color::from_rgb(0, 0, 255));
+        ctx.send(CreateReply::default().embed(embed)).await?;
+        return Ok(());
+    };
+    let normalized = db::normalize_tag(&tag).unwrap_or_default();
+    let to_find = normalized.clone();
+    let tagged = ctx
+        .data()
+        .db
+        .get()
+        .await?
+        .interact(move |x| {
+            use crate::schema::{object_tags, objects};
+            use diesel::prelude::*;
+            objects::table
+                .inner_join(object_tags::table)
+                .filter(objects::user.eq(uid))
+                .filter(object_tags::tag.eq(to_find))
+                .order_by(objects::created_unix.desc())
+                .select(Object::as_select())
+                .load(x)
+        })
+        .await
+        .unwrap()?;
+    if tagged.is_empty() {
+        ctx.reply(format!("None of your objects are tagged with `{normalized}`"))
+            .await?;
+        return Ok(());
+    }
+    ctx.send(embed_objects(ctx.data(), tagged, uid).await?).await?;
+    Ok(())
+}
+
+#[poise::command(slash_command, subcommands("share_object", "share_collection"), subcommand_required, install_context = "Guild|User", interaction_context = "Guild|BotDm|PrivateChannel")]
+async fn share(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+#[poise::command(slash_command, rename = "object")]
+async fn share_object(
+    ctx: Context<'_>,
+    #[description = "Object to share"]
+    #[autocomplete = "autocomplete_object"]
+    object: i32,
+    #[description = "User to share it with"] user: serenity::User,
+    #[description = "What they can do with it"] access: ShareAccess,
+) -> Result<(), Error> {
+    let Some(object) = load_object(ctx, object, Access::Owner).await? else {
+        return Ok(());
+    };
+    if user.id == ctx.author().id || user.bot {
+        ctx.send(
+            CreateReply::default()
+                .content("You can only share objects with other users")
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    }
+    ensure_user(ctx.data(), &user).await?;
+    let access = Access::from(access);
+    let (oid, grantee) = (object.id, user.id.get() as i64);
+    ctx.data()
+        .db
+        .get()
+        .await?
+        .interact(move |x| sharing::share_object(oid, grantee, access, x))
+        .await
+        .unwrap()?;
+    ctx.reply(format!(
+        "Shared {} with {} ({})",
+        object.name,
+        user.mention(),
+        access.label()
+    ))
+    .await?;
+    Ok(())
+}
+
+#[poise::command(slash_command, rename = "collection")]
+async fn share_collection(
+    ctx: Context<'_>,
+    #[description = "Collection to share, along with every object in it"]
+    #[autocomplete = "autocomplete_collection"]
+    collection: i32,
+    #[description = "User to share it with"] user: serenity::User,
+    #[description = "What they can do with it"] access: ShareAccess,
+) -> Result<(), Error> {
+    let Some((collection, _)) = load_collection(ctx, collection, Access::Owner).await? else {
+        return Ok(());
+    };
+    if user.id == ctx.author().id || user.bot {
+        ctx.send(
+            CreateReply::default()
+                .content("You can only share collections with other users")
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    }
+    ensure_user(ctx.data(), &user).await?;
+    let access = Access::from(access);
+    let (cid, grantee) = (collection.id, user.id.get() as i64);
+    ctx.data()
+        .db
+        .get()
+        .await?
+        .interact(move |x| sharing::share_collection(cid, grantee, access, x))
+        .await
+        .unwrap()?;
+    ctx.reply(format!(
+        "Shared {} with {} ({})",
+        collection.name,
+        user.mention(),
+        access.label()
+    ))
+    .await?;
+    Ok(())
+}
+
+#[poise::command(slash_command, subcommands("unshare_object", "unshare_collection"), subcommand_required, install_context = "Guild|User", interaction_context = "Guild|BotDm|PrivateChannel")]
+async fn unshare(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+#[poise::command(slash_command, rename = "object")]
+async fn unshare_object(
+    ctx: Context<'_>,
+    #[description = "Object to stop sharing"]
+    #[autocomplete = "autocomplete_object"]
+    object: i32,
+    #[description = "User to take access away from"] user: serenity::User,
+) -> Result<(), Error> {
+    let Some(object) = load_object(ctx, object, Access::Owner).await? else {
+        return Ok(());
+    };
+    let (oid, grantee) = (object.id, user.id.get() as i64);
+    let removed = ctx
+        .data()
+        .db
+        .get()
+        .await?
+        .interact(move |x| sharing::unshare_object(oid, grantee, x))
+        .await
+        .unwrap()?;
+    let message = match removed {
+        0 => format!("{} wasn't shared with {}", object.name, user.mention()),
+        _ => format!("{} no longer has access to {}", user.mention(), object.name),
+    };
+    ctx.reply(message).await?;
+    Ok(())
+}
+
+#[poise::command(slash_command, rename = "collection")]
+async fn unshare_collection(
+    ctx: Context<'_>,
+    #[description = "Collection to stop sharing"]
+    #[autocomplete = "autocomplete_collection"]
+    collection: i32,
+    #[description = "User to take access away from"] user: serenity::User,
+) -> Result<(), Error> {
+    let Some((collection, _)) = load_collection(ctx, collection, Access::Owner).await? else {
+        return Ok(());
+    };
+    let (cid, grantee) = (collection.id, user.id.get() as i64);
+    let removed = ctx
+        .data()
+        .db
+        .get()
+        .await?
+        .interact(move |x| sharing::unshare_collection(cid, grantee, x))
+        .await
+        .unwrap()?;
+    let message = match removed {
+        0 => format!("{} wasn't shared with {}", collection.name, user.mention()),
+        _ => format!("{} no longer has access to {}", user.mention(), collection.name),
+    };
+    ctx.reply(message).await?;
+    Ok(())
+}
+
+// Lists uploads even after the local object is gone, that's when the remote copies matter most
+#[poise::command(slash_command, install_context = "Guild|User", interaction_context = "Guild|BotDm|PrivateChannel")]
+async fn uploads(ctx: Context<'_>) -> Result<(), Error> {
+    let uid = ctx.author().id.get() as i64;
+    let uploads = ctx
+        .data()
+        .db
+        .get()
+        .await?
+        .interact(move |x| db::Upload::recent(uid, 25, x))
+        .await
+        .unwrap()?;
+    if uploads.is_empty() {
+        ctx.send(
+            CreateReply::default()
+                .content("You haven't uploaded anything to a destination yet")
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    }
+    let mut listing = String::new();
+    for upload in &uploads {
+        let object = match upload.object_id {
+            Some(object_id) => format!("`[{object_id}]`"),
+            None => "(deleted)".to_owned(),
+        };
+        let line = format!(
+            "{} {}: {}\n",
+            object,
+            upload.object_name.chars().take(60).collect::<String>(),
+            upload_line(upload)
+        );
+        if listing.len() + line.len() > 4000 {
+            listing.push_str("...");
+            break;
+        }
+        listing.push_str(&line);
+    }
     let embed = CreateEmbed::new()
-        .title(object.name)
-        .description(humansize::format_size(
-            object.size as u64,
-            humansize::DECIMAL,
-        ))
-        .color(serenity::Color::from_rgb(0, 0, 255))
-        .field("Expires", format!("In {days_until_expiry} days"), false);
-    let dropdown = CreateSelectMenu::new(
-        format!("Object:{}", object.id),
-        serenity::CreateSelectMenuKind::String {
-            options: vec![
-                CreateSelectMenuOption::new("Upload to discord", "upload"),
-                CreateSelectMenuOption::new("Delete", "delete"),
-                CreateSelectMenuOption::new("Compress to discord size (10mb)", "compress"),
-                CreateSelectMenuOption::new("Compress to discord size (50mb nitro)", "compress50"),
-                CreateSelectMenuOption::new("Upload to XBackbone", "xbackbone"),
-                CreateSelectMenuOption::new("--", "--"),
-            ],
-        },
-    );
-    let action_row = CreateActionRow::SelectMenu(dropdown);
-    Ok(CreateReply {
-        reply: true,
-        components: Some(vec![action_row]),
-        embeds: vec![embed],
-        ..Default::default()
-    })
+        .title("Your uploads")
+        .description(listing)
+        .color(serenity::Color::from_rgb(0, 0, 255));
+    let mut reply = CreateReply::default().embed(embed).ephemeral(true);
+    if let Some(menu) = delete_upload_menu(&uploads, uid) {
+        reply = reply.components(vec![menu]);
+    }
+    ctx.send(reply).await?;
+    Ok(())
 }
 
 #[poise::command(slash_command, install_context = "Guild|User", interaction_context = "Guild|BotDm|PrivateChannel")]
-async fn ytdlp(ctx: Context<'_>, #[description = "Video URL"] url: String) -> Result<(), Error> {
+async fn shared_with_me(ctx: Context<'_>) -> Result<(), Error> {
     ctx.defer().await?;
-    let downloader = YoutubeDownloader {};
-    let (name, tmp) = downloader.download(url).await?;
-    let path = tmp.into_temp_path().keep()?;
-    let metadata = std::fs::metadata(&path)?;
-    let size = metadata.len() as i64;
-    let expiry_time = SystemTime::now() + Duration::from_secs(60 * 60 * 24 * 7);
-    let expiry_unix = expiry_time
-        .duration_since(SystemTime::UNIX_EPOCH)?
-        .as_secs() as i64;
-    let object = NewObject {
-        path: path.to_string_lossy().to_string(),
-        name,
-        size,
-        expiry_unix,
-        user: ctx.author().id.get() as i64,
-    };
-    let object = ctx
+    let uid = ctx.author().id.get() as i64;
+    let (shared, owners) = ctx
         .data()
         .db
         .get()
         .await?
         .interact(move |x| {
+            use crate::schema::users::dsl::*;
             use diesel::prelude::*;
-            diesel::insert_into(crate::schema::objects::table)
-                .values(&object)
-                .returning(Object::as_returning())
-                .get_result(x)
+            let shared = sharing::shared_with(uid, x)?;
+            let owner_ids = shared
+                .objects
+                .iter()
+                .map(|(object, _)| object.user)
+                .chain(shared.collections.iter().map(|(collection, _)| collection.user))
+                .collect::<Vec<_>>();
+            let owners = users
+                .filter(snowflake.eq_any(owner_ids))
+                .select((snowflake, name_cached))
+                .load::<(i64, Option<String>)>(x)?
+                .into_iter()
+                .collect::<HashMap<_, _>>();
+            Ok::<_, diesel::result::Error>((shared, owners))
         })
         .await
         .unwrap()?;
-    let respond = embed_object(object)?;
-    ctx.send(respond).await?;
+    let owner_name = |owner: i64| match owners.get(&owner) {
+        Some(Some(name)) => name.clone(),
+        _ => owner.to_string(),
+    };
+    let mut objects_listing = String::new();
+    for (object, access) in &shared.objects {
+        let line = format!(
+            "`[{}]` {} ({}, {} from {})\n",
+            object.id,
+            object.name.chars().take(60).collect::<String>(),
+            humansize::format_size(object.size as u64, humansize::DECIMAL),
+            access.label(),
+            owner_name(object.user)
+        );
+        // Embed field values are capped at 1024 characters
+        if objects_listing.len() + line.len() > 1000 {
+            objects_listing.push_str("...");
+            break;
+        }
+        objects_listing.push_str(&line);
+    }
+    let mut collections_listing = String::new();
+    for (collection, access) in &shared.collections {
+        let line = format!(
+            "`[{}]` {} ({} from {})\n",
+            collection.id,
+            collection.name.chars().take(60).collect::<String>(),
+            access.label(),
+            owner_name(collection.user)
+        );
+        if collections_listing.len() + line.len() > 1000 {
+            collections_listing.push_str("...");
+            break;
+        }
+        collections_listing.push_str(&line);
+    }
+    let mut embed = CreateEmbed::new()
+        .title("Shared with you")
+        .color(serenity::Color::from_rgb(0, 0, 255));
+    if objects_listing.is_empty() && collections_listing.is_empty() {
+        embed = embed.description("Nothing has been shared with you yet");
+    }
+    if !objects_listing.is_empty() {
+        embed = embed.field("Objects", objects_listing, false);
+    }
+    if !collections_listing.is_empty() {
+        embed = embed.field(
+            "Collections",
+            format!("{collections_listing}Open them with /collection show"),
+            false,
+        );
+    }
+    let mut reply = CreateReply::default().embed(embed);
+    if !shared.objects.is_empty() {
+        // Select menus hold at most 25 options
+        let options = shared
+            .objects
+            .iter()
+            .take(25)
+            .map(|(object, _)| {
+                let label: String = object.name.chars().take(100).collect();
+                CreateSelectMenuOption::new(label, object.id.to_string()).description(
+                    humansize::format_size(object.size as u64, humansize::DECIMAL),
+                )
+            })
+            .collect();
+        let dropdown = CreateSelectMenu::new(
+            "OpenObject",
+            serenity::CreateSelectMenuKind::String { options },
+        )
+        .placeholder("Open object");
+        reply = reply.components(vec![CreateActionRow::SelectMenu(dropdown)]);
+    }
+    ctx.send(reply).await?;
     Ok(())
 }
 
-#[poise::command(slash_command, install_context = "Guild|User", interaction_context = "Guild|BotDm|PrivateChannel")]
-async fn my_objects(ctx: Context<'_>) -> Result<(), Error> {
+async fn is_admin(ctx: Context<'_>) -> Result<bool, Error> {
+    let user = ensure_user(ctx.data(), ctx.author()).await?;
+    if !user.is_admin() {
+        ctx.send(
+            CreateReply::default()
+                .content("Only admins can use this command")
+                .ephemeral(true),
+        )
+        .await?;
+    }
+    Ok(user.is_admin())
+}
+
+#[poise::command(slash_command, check = "is_admin", install_context = "Guild|User", interaction_context = "Guild|BotDm|PrivateChannel")]
+async fn set_role(
+    ctx: Context<'_>,
+    #[description = "User to change"] user: serenity::User,
+    #[description = "New role, e.g. user or admin"] role: String,
+) -> Result<(), Error> {
     ctx.defer().await?;
-    let time_unix_now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
-    let user_id = ctx.author().id.get() as i64;
-    let objects = ctx
-        .data()
+    ensure_user(ctx.data(), &user).await?;
+    let uid = user.id.get() as i64;
+    let new_role = role.trim().to_lowercase();
+    let role_to_set = new_role.clone();
+    ctx.data()
         .db
         .get()
         .await?
         .interact(move |x| {
-            use crate::schema::objects::dsl::*;
+            use crate::schema::users::dsl::*;
             use diesel::prelude::*;
-            diesel::QueryDsl::filter(objects, user.eq(user_id))
-                .select(Object::as_select())
-                .order_by(expiry_unix.desc())
-                .filter(expiry_unix.gt(time_unix_now))
-                .load(x)
+            diesel::update(users.find(uid))
+                .set(role.eq(role_to_set))
+                .execute(x)
         })
         .await
         .unwrap()?;
-    let mut str = "You own the following objects: ".to_owned();
-    for object in objects {
-        str.push_str(&format!("([{}]-{})\n", object.id, object.name));
-    }
-    ctx.reply(str).await?;
+    ctx.reply(format!("{} now has the role `{}`", user.name, new_role))
+        .await?;
     Ok(())
 }
 
-#[poise::command(slash_command, install_context = "Guild|User", interaction_context = "Guild|BotDm|PrivateChannel")]
-async fn get_object(ctx: Context<'_>, #[description = "Object ID"] oid: i32) -> Result<(), Error> {
+#[poise::command(slash_command, check = "is_admin", install_context = "Guild|User", interaction_context = "Guild|BotDm|PrivateChannel")]
+async fn set_retention(
+    ctx: Context<'_>,
+    #[description = "Role to configure"] role: String,
+    #[description = "Maximum days objects are kept, leave empty for no limit"] max_days: Option<u32>,
+) -> Result<(), Error> {
     ctx.defer().await?;
-    let uid = ctx.author().id.get() as i64;
-    let object = ctx
+    let limit = db::RetentionLimit {
+        role: role.trim().to_lowercase(),
+        max_days: max_days.map(|days| days as i32),
+    };
+    let message = match max_days {
+        Some(days) => format!("Objects of `{}` users are now kept for at most {days} days", limit.role),
+        None => format!("Objects of `{}` users can now be kept forever", limit.role),
+    };
+    ctx.data()
+        .db
+        .get()
+        .await?
+        .interact(move |x| {
+            use diesel::prelude::*;
+            diesel::replace_into(crate::schema::retention_limits::table)
+                .values(&limit)
+                .execute(x)
+        })
+        .await
+        .unwrap()?;
+    ctx.reply(message).await?;
+    Ok(())
+}
+
+#[poise::command(slash_command, check = "is_admin", install_context = "Guild|User", interaction_context = "Guild|BotDm|PrivateChannel")]
+async fn set_quota(
+    ctx: Context<'_>,
+    #[description = "User to change"] user: serenity::User,
+    #[description = "Quota in megabytes, leave empty for the default"] megabytes: Option<u64>,
+) -> Result<(), Error> {
+    ctx.defer().await?;
+    ensure_user(ctx.data(), &user).await?;
+    let uid = user.id.get() as i64;
+    let quota = megabytes.map(|megabytes| (megabytes * 1_000_000) as i64);
+    ctx.data()
+        .db
+        .get()
+        .await?
+        .interact(move |x| {
+            use crate::schema::users::dsl::*;
+            use diesel::prelude::*;
+            diesel::update(users.find(uid))
+                .set(bytes_allowed.eq(quota))
+                .execute(x)
+        })
+        .await
+        .unwrap()?;
+    let quota = humansize::format_size(
+        quota.unwrap_or_else(db::default_quota) as u64,
+        humansize::DECIMAL,
+    );
+    ctx.reply(format!("{} now has a quota of {}", user.name, quota))
+        .await?;
+    Ok(())
+}
+
+// Run after moving the previous CREDENTIALS_KEY into CREDENTIALS_OLD_KEYS, the old key can be dropped once this succeeds
+#[poise::command(slash_command, check = "is_admin", install_context = "Guild|User", interaction_context = "Guild|BotDm|PrivateChannel")]
+async fn rotate_credentials(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.defer_ephemeral().await?;
+    let credentials = ctx.data().credentials.clone();
+    if !credentials.enabled() {
+        ctx.reply("CREDENTIALS_KEY is not set, there is no key to encrypt with")
+            .await?;
+        return Ok(());
+    }
+    let rotated = ctx
         .data()
+        .db
+        .get()
+        .await?
+        .interact(move |x| credentials::rotate(&credentials, x))
+        .await
+        .unwrap();
+    let message = match rotated {
+        Ok(rotation) if rotation.skipped.is_empty() => format!(
+            "Re-encrypted {} stored credentials with the current key",
+            rotation.rotated
+        ),
+        Ok(rotation) => {
+            let mut message = format!(
+                "Re-encrypted {} stored credentials with the current key. {} couldn't be read \
+                 and still need their old key:",
+                rotation.rotated,
+                rotation.skipped.len()
+            );
+            for skipped in &rotation.skipped {
+                // Messages are capped at 2000 characters
+                if message.len() + skipped.len() > 1900 {
+                    message.push_str("\n...");
+                    break;
+                }
+                message.push_str(&format!("\n{skipped}"));
+            }
+            message
+        }
+        Err(e) => format!("Nothing was changed, rotating failed: {e}"),
+    };
+    ctx.reply(message).await?;
+    Ok(())
+}
+
+async fn open_object(
+    ctx: &serenity::Context,
+    component: &serenity::ComponentInteraction,
+    data: &Data,
+) -> Result<(), Error> {
+    let object_id: i32 = match &component.data.kind {
+        ComponentInteractionDataKind::StringSelect { values } => values.first().unwrap().parse()?,
+        _ => anyhow::bail!("Invalid component type"),
+    };
+    let uid = component.user.id.get() as i64;
+    let object = data
         .db
         .get()
         .await?
         .interact(move |x| {
             use crate::schema::objects::dsl::*;
             use diesel::prelude::*;
-            diesel::QueryDsl::filter(objects.find(oid), user.eq(uid))
+            if sharing::object_access(object_id, uid, x)?.is_none() {
+                return Ok(None);
+            }
+            objects
+                .find(object_id)
                 .select(Object::as_select())
                 .first(x)
+                .optional()
         })
         .await
         .unwrap()?;
-    let create_reply = embed_object(object)?;
-    ctx.send(create_reply).await?;
+    let Some(object) = object else {
+        component
+            .create_response(&ctx, ephemeral(Access::Read.denied_message("object")))
+            .await?;
+        return Ok(());
+    };
+    let reply = embed_object(data, object, uid).await?;
+    component
+        .create_response(
+            &ctx,
+            serenity::CreateInteractionResponse::Message(
+                serenity::CreateInteractionResponseMessage::new()
+                    .embeds(reply.embeds)
+                    .components(reply.components.unwrap()),
+            ),
+        )
+        .await?;
     Ok(())
 }
 
-#[poise::command(slash_command, install_context = "Guild|User", interaction_context = "BotDm")]
-async fn upload_xbackbone_config(ctx: Context<'_>, json_text: String) -> Result<(), Error> {
-    ctx.defer().await?;
-    let uid = ctx.author().id.get() as i64;
-    ctx.data()
+async fn add_to_collection(
+    ctx: &serenity::Context,
+    component: &serenity::ComponentInteraction,
+    data: &Data,
+    oid: i32,
+) -> Result<(), Error> {
+    let collection_id: i32 = match &component.data.kind {
+        ComponentInteractionDataKind::StringSelect { values } => values.first().unwrap().parse()?,
+        _ => anyhow::bail!("Invalid component type"),
+    };
+    let uid = component.user.id.get() as i64;
+    let message = data
         .db
         .get()
         .await?
         .interact(move |x| {
             use diesel::prelude::*;
-            diesel::insert_into(crate::schema::sharex_config::table)
-                .values(
-                    SharexConfig {
-                        user_id: uid,
-                        json: json_text.clone(),
-                    }
-                )
-                .execute(x)
+            let object = crate::schema::objects::table
+                .find(oid)
+                .filter(crate::schema::objects::user.eq(uid))
+                .select(Object::as_select())
+                .first(x)
+                .optional()?;
+            let collection = db::Collection::find_owned(collection_id, uid, x)?;
+            let (Some(object), Some(collection)) = (object, collection) else {
+                return Ok::<_, diesel::result::Error>("That object or collection no longer exists".to_owned());
+            };
+            Ok(match collection.add_objects(&[oid], x)? {
+                0 => format!("{} is already in {}", object.name, collection.name),
+                _ => format!("Added {} to {}", object.name, collection.name),
+            })
         })
         .await
         .unwrap()?;
-    ctx.reply("Received sharex config").await?;
+    component
+        .create_response(
+            &ctx,
+            serenity::CreateInteractionResponse::UpdateMessage(
+                serenity::CreateInteractionResponseMessage::new()
+                    .content(message)
+                    .components(vec![]),
+            ),
+        )
+        .await?;
+    Ok(())
+}
+
+async fn upload_to(
+    ctx: &serenity::Context,
+    component: &serenity::ComponentInteraction,
+    data: &Data,
+    oid: i32,
+) -> Result<(), Error> {
+    let destination_id: i32 = match &component.data.kind {
+        ComponentInteractionDataKind::StringSelect { values } => values.first().unwrap().parse()?,
+        _ => anyhow::bail!("Invalid component type"),
+    };
+    let uid = component.user.id.get() as i64;
+    let access = data
+        .db
+        .get()
+        .await?
+        .interact(move |x| sharing::object_access(oid, uid, x))
+        .await
+        .unwrap()?;
+    if access.is_none() {
+        component
+            .create_response(&ctx, ephemeral(Access::Read.denied_message("object")))
+            .await?;
+        return Ok(());
+    }
+    let spec = JobSpec::Upload {
+        object_id: oid,
+        destination: Some(destination_id),
+    };
+    run_job_in_followup(ctx, component, data, spec).await
+}
+
+fn delete_upload_menu(uploads: &[db::Upload], uid: i64) -> Option<CreateActionRow> {
+    let options = uploads
+        .iter()
+        .filter(|upload| upload.can_delete(uid))
+        .take(25)
+        .map(|upload| {
+            let label: String = format!("{} on {}", upload.object_name, upload.destination_name)
+                .chars()
+                .take(100)
+                .collect();
+            let description: String = upload.url.chars().take(100).collect();
+            CreateSelectMenuOption::new(label, upload.id.to_string()).description(description)
+        })
+        .collect::<Vec<_>>();
+    if options.is_empty() {
+        return None;
+    }
+    let dropdown = CreateSelectMenu::new(
+        "DeleteUpload",
+        serenity::CreateSelectMenuKind::String { options },
+    )
+    .placeholder("Remote copy to delete");
+    Some(CreateActionRow::SelectMenu(dropdown))
+}
+
+async fn delete_upload(
+    ctx: &serenity::Context,
+    component: &serenity::ComponentInteraction,
+    data: &Data,
+) -> Result<(), Error> {
+    let upload_id: i32 = match &component.data.kind {
+        ComponentInteractionDataKind::StringSelect { values } => values.first().unwrap().parse()?,
+        _ => anyhow::bail!("Invalid component type"),
+    };
+    let uid = component.user.id.get() as i64;
+    let upload = data
+        .db
+        .get()
+        .await?
+        .interact(move |x| {
+            use crate::schema::uploads::dsl::*;
+            use diesel::prelude::*;
+            uploads
+                .find(upload_id)
+                .select(db::Upload::as_select())
+                .first(x)
+                .optional()
+        })
+        .await
+        .unwrap()?
+        .filter(|upload| upload.can_delete(uid));
+    let Some(upload) = upload else {
+        component
+            .create_response(&ctx, ephemeral("That remote copy was already deleted"))
+            .await?;
+        return Ok(());
+    };
+    component.defer(&ctx).await?;
+    let deletion_url = data
+        .credentials
+        .decrypt(upload.deletion_url.as_deref().unwrap_or_default())?;
+    let message = match sharex::delete_remote(&deletion_url).await {
+        Ok(()) => {
+            data.db
+                .get()
+                .await?
+                .interact(move |x| {
+                    use crate::schema::uploads::dsl::*;
+                    use diesel::prelude::*;
+                    diesel::update(uploads.find(upload_id))
+                        .set(deleted_unix.eq(unix_now()))
+                        .execute(x)
+                })
+                .await
+                .unwrap()?;
+            format!("Deleted the copy on {}", upload.destination_name)
+        }
+        Err(e) => format!("Deleting the copy on {} failed: {}", upload.destination_name, e),
+    };
+    component
+        .edit_response(
+            &ctx,
+            serenity::EditInteractionResponse::new()
+                .content(message)
+                .components(vec![]),
+        )
+        .await?;
     Ok(())
 }
 
@@ -273,44 +2782,164 @@ async fn event_handler(
         serenity::FullEvent::InteractionCreate { interaction } => {
             match interaction {
                 Interaction::Component(component) => {
+                    if component.data.custom_id == "OpenObject" {
+                        return open_object(ctx, component, data).await;
+                    }
+                    if let Some(state) = BrowserState::parse(&component.data.custom_id) {
+                        return update_browser(ctx, component, data, state).await;
+                    }
+                    if let ComponentInteractionDataKind::StringSelect { values } = &component.data.kind {
+                        let state = values
+                            .first()
+                            .and_then(|value| browser::parse_sort_menu(&component.data.custom_id, value));
+                        if let Some(state) = state {
+                            return update_browser(ctx, component, data, state).await;
+                        }
+                    }
+                    if let Some(job_id) = component.data.custom_id.strip_prefix("Cancel:") {
+                        let job_id: i32 = job_id.parse()?;
+                        let message = match data
+                            .jobs
+                            .cancel(&data.db, job_id, component.user.id.get())
+                            .await?
+                        {
+                            CancelResult::Cancelled => None,
+                            CancelResult::NotOwner => {
+                                Some("Only the user who started this job can cancel it")
+                            }
+                            CancelResult::NotRunning => Some("This job has already finished"),
+                        };
+                        let response = match message {
+                            None => serenity::CreateInteractionResponse::Acknowledge,
+                            Some(message) => serenity::CreateInteractionResponse::Message(
+                                serenity::CreateInteractionResponseMessage::new()
+                                    .content(message)
+                                    .ephemeral(true),
+                            ),
+                        };
+                        component.create_response(&ctx, response).await?;
+                        return Ok(());
+                    }
+                    if let Some(object_id) = component.data.custom_id.strip_prefix("AddToCollection:") {
+                        return add_to_collection(ctx, component, data, object_id.parse()?).await;
+                    }
+                    if let Some(object_id) = component.data.custom_id.strip_prefix("UploadTo:") {
+                        return upload_to(ctx, component, data, object_id.parse()?).await;
+                    }
+                    if component.data.custom_id.starts_with("DeleteUpload") {
+                        return delete_upload(ctx, component, data).await;
+                    }
                     let object_id = component.data.custom_id.strip_prefix("Object:");
                     if object_id.is_none() {
                         return Ok(());
                     };
                     let object_id: i32 = object_id.unwrap().parse()?;
-                    let object = data
+                    let uid = component.user.id.get() as i64;
+                    let (object, access) = data
                         .db
                         .get()
                         .await?
                         .interact(move |x| {
                             use crate::schema::objects::dsl::*;
                             use diesel::prelude::*;
-                            objects.find(object_id).select(Object::as_select()).first(x)
+                            let object = objects
+                                .find(object_id)
+                                .select(Object::as_select())
+                                .first(x)
+                                .optional()?;
+                            let access = sharing::object_access(object_id, uid, x)?;
+                            Ok::<_, diesel::result::Error>((object, access))
                         })
                         .await
                         .unwrap()?;
-                    if object.user != component.user.id.get() as i64 {
+                    let (Some(object), Some(access)) = (object, access) else {
+                        component
+                            .create_response(&ctx, ephemeral(Access::Read.denied_message("object")))
+                            .await?;
                         return Ok(());
-                    }
+                    };
                     let chosen_action = match &component.data.kind {
                         ComponentInteractionDataKind::StringSelect { values } => {
                             values.get(0).unwrap()
                         }
                         _ => anyhow::bail!("Invalid component type"),
                     };
+                    let required = match chosen_action.as_str() {
+                        "extend" | "pin" => Access::Manage,
+                        // Deleting is permanent and collections are private to their owner
+                        "delete" | "collection" => Access::Owner,
+                        _ => Access::Read,
+                    };
+                    if access < required {
+                        component
+                            .create_response(&ctx, ephemeral(required.denied_message("object")))
+                            .await?;
+                        return Ok(());
+                    }
                     match chosen_action.as_str() {
-                        "delete" => {
-                            std::fs::remove_file(&object.path)?;
-                            data.db
+                        "extend" | "pin" => {
+                            // Retention depends on the owner's role, not on whoever clicked
+                            let owner = object.user;
+                            let user = data
+                                .db
+                                .get()
+                                .await?
+                                .interact(move |x| User::get(owner, x))
+                                .await
+                                .unwrap()?;
+                            let max_retention = data
+                                .db
+                                .get()
+                                .await?
+                                .interact(move |x| user.max_retention(x))
+                                .await
+                                .unwrap()?;
+                            let new_expiry =
+                                extended_expiry(&object, chosen_action == "pin", max_retention);
+                            let new_expiry = match new_expiry {
+                                Ok(new_expiry) => new_expiry,
+                                Err(message) => {
+                                    component
+                                        .create_response(
+                                            &ctx,
+                                            serenity::CreateInteractionResponse::Message(
+                                                serenity::CreateInteractionResponseMessage::new()
+                                                    .content(message)
+                                                    .ephemeral(true),
+                                            ),
+                                        )
+                                        .await?;
+                                    return Ok(());
+                                }
+                            };
+                            let object = data
+                                .db
                                 .get()
                                 .await?
                                 .interact(move |x| {
                                     use crate::schema::objects::dsl::*;
                                     use diesel::prelude::*;
-                                    diesel::delete(objects.find(object_id)).execute(x)
+                                    diesel::update(objects.find(object_id))
+                                        .set(expiry_unix.eq(new_expiry))
+                                        .returning(Object::as_returning())
+                                        .get_result(x)
                                 })
                                 .await
                                 .unwrap()?;
+                            let reply = embed_object(data, object, uid).await?;
+                            component
+                                .create_response(
+                                    &ctx,
+                                    serenity::CreateInteractionResponse::UpdateMessage(
+                                        serenity::CreateInteractionResponseMessage::new()
+                                            .embeds(reply.embeds)
+                                            .components(reply.components.unwrap()),
+                                    ),
+                                )
+                                .await?;
+                        }
+                        "delete" => {
+                            delete_objects(data, vec![object_id]).await?;
                             component
                                 .create_response(
                                     &ctx,
@@ -319,37 +2948,15 @@ async fn event_handler(
                                 .await?;
                         }
                         "compress" | "compress50" => {
-                            let compress = async |max_size: u64| {
-                                // Run ffmpeg pass on the file
-                                println!("Compressing to {}, defer", max_size);
-                                component.defer(&ctx).await?;
-                                let mut pp_orchestrator = PostProcessOrchestrator::new(
-                                    component.user.clone(),
-                                    object.clone(),
-                                    data.clone(),
-                                );
-                                let ffmpeg = FFMpegResizeProcessor { max_size };
-                                pp_orchestrator.add_post_processor(&ffmpeg, true);
-                                println!("Running compress pass");
-                                pp_orchestrator.process().await?;
-                                let new_object = pp_orchestrator.object;
-                                let embed = embed_object(new_object)?;
-                                component
-                                    .create_followup(
-                                        &ctx,
-                                        CreateInteractionResponseFollowup::new()
-                                            .embeds(embed.embeds)
-                                            .components(embed.components.unwrap()),
-                                    )
-                                    .await?;
-                                Ok::<(), anyhow::Error>(())
+                            let max_size = match chosen_action.as_str() {
+                                "compress" => 9_500_000,
+                                _ => 49_000_000,
                             };
-                            match chosen_action.as_str() {
-                                "compress" => compress(9_500_000).await?,
-                                "compress50" => compress(49_000_000).await?,
-                                _ => anyhow::bail!("Invalid component type"),
-                            }
-                            println!("Finished compress pass");
+                            let spec = JobSpec::Compress {
+                                object_id,
+                                max_size,
+                            };
+                            run_job_in_followup(ctx, component, data, spec).await?;
                         }
                         "upload" => {
                             component.defer(&ctx).await?;
@@ -361,60 +2968,136 @@ async fn event_handler(
                                 )
                                 .await?;
                         }
+                        // Menus sent before destinations existed upload to the default one
                         "xbackbone" => {
-                            component.defer(&ctx).await?;
-                            let uid = component.user.id.get() as i64;
-                            let sharex_json = data
+                            let spec = JobSpec::Upload {
+                                object_id,
+                                destination: None,
+                            };
+                            run_job_in_followup(ctx, component, data, spec).await?;
+                        }
+                        // Menus sent before the picker name the destination directly
+                        action if action.starts_with("destination:") => {
+                            let spec = JobSpec::Upload {
+                                object_id,
+                                destination: Some(action["destination:".len()..].parse()?),
+                            };
+                            run_job_in_followup(ctx, component, data, spec).await?;
+                        }
+                        "link" => {
+                            let message = match &data.links {
+                                Some(links) => {
+                                    let (url, expires) = links.sign(&object);
+                                    format!("{url}\nThis link stops working <t:{expires}:R>")
+                                }
+                                None => "Direct links are not enabled on this bot".to_owned(),
+                            };
+                            component.create_response(&ctx, ephemeral(message)).await?;
+                        }
+                        "collection" => {
+                            let uid = object.user;
+                            let collections = data
                                 .db
                                 .get()
                                 .await?
                                 .interact(move |x| {
-                                    use crate::schema::sharex_config::dsl::*;
+                                    use crate::schema::collections::dsl::*;
                                     use diesel::prelude::*;
-                                    sharex_config
-                                        .find(uid)
-                                        .select(SharexConfig::as_select())
-                                        .first(x)
+                                    collections
+                                        .filter(user.eq(uid))
+                                        .order_by(id.desc())
+                                        .limit(25)
+                                        .select(db::Collection::as_select())
+                                        .load(x)
                                 })
                                 .await
                                 .unwrap()?;
-                            let sharex_json: serde_json::Result<sharex::XBackboneShareXData> =
-                                serde_json::from_str(&sharex_json.json);
-                            if let Err(e) = sharex_json {
-                                println!("Error parsing sharex json: {}", e);
-                                component
-                                    .create_followup(
-                                        &ctx,
-                                        CreateInteractionResponseFollowup::new().add_embed(
-                                            CreateEmbed::new()
-                                                .title("Error parsing sharex json")
-                                                .description(format!("{}", e))
-                                                .color(serenity::Color::from_rgb(255, 0, 0)),
-                                        ),
-                                    )
-                                    .await?;
-                                return Ok(());
-                            }
-                            let sharex_json = sharex_json.unwrap();
-                            println!("Uploading to xbackbone");
-                            // Post a reqwest to upload
-                            let client = reqwest::Client::new();
-                            let form = reqwest::multipart::Form::new()
-                                .text("token", sharex_json.arguments.token)
-                                .file("file", &object.path)
+                            let message = if collections.is_empty() {
+                                serenity::CreateInteractionResponseMessage::new().content(
+                                    "You don't have any collections yet, create one with /collection create",
+                                )
+                            } else {
+                                let options = collections
+                                    .iter()
+                                    .map(|collection| {
+                                        let label: String = collection.name.chars().take(100).collect();
+                                        CreateSelectMenuOption::new(label, collection.id.to_string())
+                                    })
+                                    .collect();
+                                let dropdown = CreateSelectMenu::new(
+                                    format!("AddToCollection:{object_id}"),
+                                    serenity::CreateSelectMenuKind::String { options },
+                                )
+                                .placeholder("Collection");
+                                serenity::CreateInteractionResponseMessage::new()
+                                    .components(vec![CreateActionRow::SelectMenu(dropdown)])
+                            };
+                            component
+                                .create_response(
+                                    &ctx,
+                                    serenity::CreateInteractionResponse::Message(message.ephemeral(true)),
+                                )
                                 .await?;
-                            let response = client
-                                .post(&sharex_json.request_url)
-                                .multipart(form)
-                                .send()
+                        }
+                        "upload_to" => {
+                            let destinations = data
+                                .db
+                                .get()
+                                .await?
+                                .interact(move |x| db::Destination::list(uid, x))
+                                .await
+                                .unwrap()?;
+                            let message = if destinations.is_empty() {
+                                serenity::CreateInteractionResponseMessage::new().content(
+                                    "You don't have any destinations yet, add one with /destination add",
+                                )
+                            } else {
+                                let options = destinations
+                                    .iter()
+                                    .take(MAX_PICKER_DESTINATIONS)
+                                    .map(|destination| {
+                                        let label: String = destination.name.chars().take(100).collect();
+                                        let option =
+                                            CreateSelectMenuOption::new(label, destination.id.to_string());
+                                        match destination.is_default {
+                                            true => option.description("Default"),
+                                            false => option,
+                                        }
+                                    })
+                                    .collect();
+                                let dropdown = CreateSelectMenu::new(
+                                    format!("UploadTo:{object_id}"),
+                                    serenity::CreateSelectMenuKind::String { options },
+                                )
+                                .placeholder("Destination");
+                                serenity::CreateInteractionResponseMessage::new()
+                                    .components(vec![CreateActionRow::SelectMenu(dropdown)])
+                            };
+                            component
+                                .create_response(
+                                    &ctx,
+                                    serenity::CreateInteractionResponse::Message(message.ephemeral(true)),
+                                )
                                 .await?;
-                            let returned_data: sharex::ReturnedData = response.json().await?;
-                            println!("Uploaded to {}", returned_data.url);
+                        }
+                        "delete_remote" => {
+                            let uploads = data
+                                .db
+                                .get()
+                                .await?
+                                .interact(move |x| db::Upload::for_object(object_id, uid, x))
+                                .await
+                                .unwrap()?;
+                            let message = match delete_upload_menu(&uploads, uid) {
+                                Some(menu) => serenity::CreateInteractionResponseMessage::new()
+                                    .components(vec![menu]),
+                                None => serenity::CreateInteractionResponseMessage::new()
+                                    .content("You have no remote copies of this object that can be deleted"),
+                            };
                             component
-                                .create_followup(
+                                .create_response(
                                     &ctx,
-                                    CreateInteractionResponseFollowup::new()
-                                        .content(returned_data.url),
+                                    serenity::CreateInteractionResponse::Message(message.ephemeral(true)),
                                 )
                                 .await?;
                         }
@@ -436,11 +3119,56 @@ async fn main() {
     let token = std::env::var("DISCORD_TOKEN").expect("missing DISCORD_TOKEN");
     let intents = serenity::GatewayIntents::non_privileged();
 
+    storage::init().expect("storage directory is not usable");
     let pool = db::create_database_pool().await;
     let pool2 = pool.clone();
+    let links = match fileserver::Links::from_env() {
+        Some(links) => match fileserver::bind().await {
+            Ok(listener) => {
+                let (pool, server_links) = (pool.clone(), links.clone());
+                tokio::spawn(async move {
+                    if let Err(e) = fileserver::serve(listener, pool, server_links).await {
+                        error!("File server stopped: {:?}", e);
+                    }
+                });
+                Some(links)
+            }
+            Err(e) => {
+                error!("File server failed to start, direct links are disabled: {:?}", e);
+                None
+            }
+        },
+        None => {
+            info!("PUBLIC_URL or LINK_SECRET not set, direct links are disabled");
+            None
+        }
+    };
+    let credentials = credentials::Credentials::from_env().expect("invalid credentials key");
+    if !credentials.enabled() {
+        info!("CREDENTIALS_KEY not set, uploader credentials are stored unencrypted");
+    }
     let framework = poise::Framework::builder()
         .options(poise::FrameworkOptions {
-            commands: vec![ytdlp(), my_objects(), get_object(), upload_xbackbone_config()],
+            commands: vec![
+                ytdlp(),
+                gallerydl(),
+                list_jobs(),
+                my_objects(),
+                search(),
+                get_object(),
+                destination(),
+                usage(),
+                collection(),
+                tag(),
+                share(),
+                unshare(),
+                shared_with_me(),
+                uploads(),
+                set_role(),
+                set_retention(),
+                set_quota(),
+                rotate_credentials(),
+            ],
             event_handler: |a, b, c, d| Box::pin(event_handler(a, b, c, d)),
             ..Default::default()
         })
@@ -451,9 +3179,15 @@ async fn main() {
                     &framework.options().commands,
                 )
                 .await?;
-                Ok(Data {
+                let data = Data {
                     db: pool,
-                })
+                    jobs: JobQueue::default(),
+                    limits: Limits::from_env(),
+                    links: links.clone(),
+                    credentials: credentials.clone(),
+                };
+                jobs::spawn_workers(data.clone(), ctx.http.clone()).await?;
+                Ok(data)
             })
         })
         .build();
@@ -466,9 +3200,13 @@ async fn main() {
                 let pool2 = pool2.clone();
                 async move {
                     info!("Running cleanup job");
-                    pool2.get().await.unwrap().interact(move |x| {
-                        db::delete_expired_files(x)
-                    }).await.unwrap().unwrap()
+                    let report = pool2.get().await.unwrap().interact(move |x| {
+                        gc::run(x)
+                    }).await.unwrap();
+                    match report {
+                        Ok(report) => info!("Cleanup finished: {}", report),
+                        Err(e) => error!("Cleanup failed: {:?}", e),
+                    }
                 }
             }
         )
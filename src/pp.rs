@@ -4,14 +4,21 @@ use std::{
 };
 
 use poise::serenity_prelude::async_trait;
+use tokio::io::AsyncBufReadExt;
 
 use crate::{
     Data,
     db::{NewObject, Object, User},
+    limits::ResourceClass,
+    progress::{Progress, ProgressReporter},
 };
 
 #[async_trait]
 pub trait PostProcessor {
+    // Which concurrency limit this pass counts against
+    fn resource_class(&self) -> ResourceClass {
+        ResourceClass::Cpu
+    }
     async fn check(&self, input: &PostProcessInput) -> bool;
     async fn process(&self, input: PostProcessInput) -> Result<PostProcessOutput, anyhow::Error>;
 }
@@ -21,6 +28,7 @@ pub struct PostProcessInput {
     pub user: User,
     pub previous_passes: Vec<TypeId>,
     pub data: Data,
+    pub progress: ProgressReporter,
 }
 
 pub struct PostProcessOutput {
@@ -28,6 +36,47 @@ pub struct PostProcessOutput {
     pub additional_passes: Vec<TypeId>,
 }
 
+// Runs an ffmpeg command that was given `-progress pipe:1`, forwarding its progress reports
+async fn run_ffmpeg(
+    command: &mut tokio::process::Command,
+    duration: f64,
+    stage: &str,
+    progress: &ProgressReporter,
+) -> Result<std::process::ExitStatus, anyhow::Error> {
+    command.stdout(std::process::Stdio::piped());
+    command.kill_on_drop(true);
+    let mut child = command.spawn()?;
+    let stdout = child.stdout.take().unwrap();
+    let mut lines = tokio::io::BufReader::new(stdout).lines();
+    let mut out_time = 0.;
+    let mut speed = None;
+    while let Ok(Some(line)) = lines.next_line().await {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        match key {
+            // Despite the name, out_time_ms is also in microseconds
+            "out_time_us" | "out_time_ms" => {
+                if let Ok(us) = value.parse::<f64>() {
+                    out_time = us / 1_000_000.;
+                }
+            }
+            "speed" => speed = value.trim().trim_end_matches('x').parse::<f64>().ok(),
+            "progress" => progress.update(Progress {
+                stage: stage.to_owned(),
+                percent: Some((out_time / duration * 100.).clamp(0., 100.)),
+                speed: speed.map(|speed| format!("{speed:.2}x")),
+                eta: speed
+                    .filter(|speed| *speed > 0.)
+                    .map(|speed| ((duration - out_time).max(0.) / speed) as u64),
+                ..Default::default()
+            }),
+            _ => {}
+        }
+    }
+    Ok(child.wait().await?)
+}
+
 pub struct FFMpegResizeProcessor {
     pub max_size: u64,
 }
@@ -61,6 +110,22 @@ impl PostProcessor for FFMpegResizeProcessor {
         let new_max_size = new_max_size as u64;
         println!("new_max_size:{}", new_max_size);
 
+        // Fail before spending minutes encoding something that can't be stored
+        let uid = input.user.snowflake;
+        let user = input
+            .data
+            .db
+            .get()
+            .await?
+            .interact(move |x| User::get(uid, x))
+            .await
+            .unwrap()?;
+        anyhow::ensure!(
+            user.bytes_remaining() as u64 >= new_max_size,
+            "Not enough storage quota left for the compressed file ({} left)",
+            humansize::format_size(user.bytes_remaining() as u64, humansize::DECIMAL)
+        );
+
         let mut target_audio_bitrate = None;
         let target_video_bitrate;
         // Ffprobe to get the current bitrates, and duration
@@ -80,7 +145,7 @@ impl PostProcessor for FFMpegResizeProcessor {
         let ffprobe_output = std::str::from_utf8(&ffprobe.stdout)?;
         let ffprobe_output: serde_json::Value = serde_json::from_str(ffprobe_output)?;
         dbg!(&ffprobe_output);
-        let duration = ffprobe_output
+        let duration_secs = ffprobe_output
             .get("format")
             .unwrap()
             .get("duration")
@@ -88,7 +153,8 @@ impl PostProcessor for FFMpegResizeProcessor {
             .as_str()
             .unwrap()
             .parse::<f64>()
-            .unwrap() as u64;
+            .unwrap();
+        let duration = duration_secs as u64;
 
         if let Some(streams) = ffprobe_output.get("streams") {
             for stream in streams.as_array().unwrap() {
@@ -113,7 +179,10 @@ impl PostProcessor for FFMpegResizeProcessor {
             anyhow::bail!("No streams found in ffprobe output");
         }
 
-        let mut object = NewObject::new_with_extension("mp4");
+        // Kept as temp paths until the encode succeeds so a failed or cancelled job leaves nothing behind
+        let output = crate::storage::staging_file("mp4")?;
+        let passlog_dir = tempfile::tempdir()?;
+        let passlog = passlog_dir.path().join("ffmpeg2pass");
 
         // FFmpeg two pass encoding
         #[cfg(target_os = "linux")]
@@ -124,9 +193,13 @@ impl PostProcessor for FFMpegResizeProcessor {
         println!("Using audio bitrate {}", target_audio_bitrate.unwrap());
         println!("Using video bitrate {}", target_video_bitrate.unwrap());
 
-        let status = tokio::process::Command::new("ffmpeg")
+        let mut pass1 = tokio::process::Command::new("ffmpeg");
+        pass1
             .arg("-y")
             .arg("-nostdin")
+            .arg("-progress")
+            .arg("pipe:1")
+            .arg("-nostats")
             .arg("-i")
             .arg(&input.file.path) // Input file
             .arg("-preset")
@@ -137,17 +210,22 @@ impl PostProcessor for FFMpegResizeProcessor {
             .arg(format!("{}", target_video_bitrate.unwrap()))
             .arg("-pass")
             .arg("1")
+            .arg("-passlogfile")
+            .arg(&passlog)
             .arg("-an") // Disable audio
             .arg("-f")
             .arg("null")
-            .arg(NULL_OUT) // Output file
-            .status()
-            .await?;
+            .arg(NULL_OUT); // Output file
+        let status = run_ffmpeg(&mut pass1, duration_secs, "Encoding (pass 1/2)", &input.progress).await?;
         anyhow::ensure!(status.success(), "ffmpeg pass 1 failed");
         // Pass 2
-        let status = tokio::process::Command::new("ffmpeg")
+        let mut pass2 = tokio::process::Command::new("ffmpeg");
+        pass2
             .arg("-y")
             .arg("-nostdin")
+            .arg("-progress")
+            .arg("pipe:1")
+            .arg("-nostats")
             .arg("-i")
             .arg(&input.file.path) // Input file
             .arg("-preset")
@@ -158,47 +236,49 @@ impl PostProcessor for FFMpegResizeProcessor {
             .arg(format!("{}", target_video_bitrate.unwrap()))
             .arg("-pass")
             .arg("2")
+            .arg("-passlogfile")
+            .arg(&passlog)
             .arg("-c:a") // Audio codec
             .arg("aac")
             .arg("-b:a") // Audio bitrate
             .arg(format!("{}", target_audio_bitrate.unwrap())) // Audio bitrate
-            .arg(&object.path) // Output file
-            .status()
-            .await?;
+            .arg(&output); // Output file
+        let status = run_ffmpeg(&mut pass2, duration_secs, "Encoding (pass 2/2)", &input.progress).await?;
         anyhow::ensure!(status.success(), "ffmpeg pass 2 failed");
 
         // Check the size of the output file
-        let metadata = std::fs::metadata(&object.path)?;
+        let metadata = std::fs::metadata(&output)?;
         println!("size:{}", metadata.len());
+        if metadata.len() > self.max_size {
+            // Retry from the original at a lower bitrate, only the final encode is stored and charged
+            return Ok(PostProcessOutput {
+                additional_passes: vec![typeid::of::<Self>()],
+                file: input.file,
+            });
+        }
 
-        let newname = input.file.name + " (compressed)";
-        object.name = newname;
-        object.expiry_unix = input.file.expiry_unix;
-        object.size = metadata.len() as i64;
-        object.user = input.user.snowflake;
+        let object = NewObject {
+            path: String::new(),
+            name: input.file.name + " (compressed)",
+            size: metadata.len() as i64,
+            expiry_unix: input.file.expiry_unix,
+            user: input.user.snowflake,
+            blob_hash: None,
+            source_url: None,
+            extractor_id: None,
+            // Compressing must not reset the retention clock
+            created_unix: input.file.created_unix,
+        };
 
         let object = input
             .data
             .db
             .get()
             .await?
-            .interact(move |x| {
-                use diesel::prelude::*;
-
-                diesel::insert_into(crate::schema::objects::table)
-                    .values(&object)
-                    .returning(Object::as_returning())
-                    .get_result(x)
-            })
+            .interact(move |x| crate::storage::store(object, output, x))
             .await
             .unwrap()?;
 
-        if metadata.len() > self.max_size {
-            return Ok(PostProcessOutput {
-                additional_passes: vec![typeid::of::<Self>()],
-                file: object,
-            });
-        }
         Ok(PostProcessOutput {
             file: object,
             additional_passes: vec![],
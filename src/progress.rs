@@ -0,0 +1,101 @@
+use std::{fmt::Display, future::Future, sync::Arc, time::Duration};
+
+use tokio::sync::watch;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Progress {
+    pub stage: String,
+    pub percent: Option<f64>,
+    pub speed: Option<String>,
+    pub eta: Option<u64>,
+    pub fragments: Option<(u64, u64)>,
+    pub entry: Option<(u64, u64)>,
+}
+
+impl Progress {
+    pub fn stage(stage: impl Into<String>) -> Self {
+        Self {
+            stage: stage.into(),
+            ..Default::default()
+        }
+    }
+}
+
+impl Display for Progress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.stage)?;
+        if let Some((current, total)) = self.entry {
+            write!(f, " [{current}/{total}]")?;
+        }
+        if let Some(percent) = self.percent {
+            write!(f, ": {percent:.1}%")?;
+        }
+        if let Some(speed) = &self.speed {
+            write!(f, " at {speed}")?;
+        }
+        if let Some(eta) = self.eta {
+            write!(f, ", ETA {}:{:02}", eta / 60, eta % 60)?;
+        }
+        if let Some((current, total)) = self.fragments {
+            write!(f, " (fragment {current}/{total})")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct ProgressReporter {
+    sender: Arc<watch::Sender<Progress>>,
+}
+
+impl ProgressReporter {
+    pub fn new() -> Self {
+        let (sender, _) = watch::channel(Progress::stage("Starting"));
+        Self {
+            sender: Arc::new(sender),
+        }
+    }
+
+    pub fn update(&self, progress: Progress) {
+        self.sender.send_if_modified(|current| {
+            if *current == progress {
+                return false;
+            }
+            *current = progress;
+            true
+        });
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<Progress> {
+        self.sender.subscribe()
+    }
+}
+
+impl Default for ProgressReporter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Runs `fut` to completion, calling `report` with the latest progress at most every few seconds
+// so edits to the Discord message stay well within rate limits
+pub async fn with_progress<T, F: Future<Output = ()>>(
+    fut: impl Future<Output = T>,
+    reporter: &ProgressReporter,
+    mut report: impl FnMut(Progress) -> F,
+) -> T {
+    let mut receiver = reporter.subscribe();
+    let mut interval = tokio::time::interval(Duration::from_secs(3));
+    tokio::pin!(fut);
+    loop {
+        tokio::select! {
+            output = &mut fut => return output,
+            _ = interval.tick() => {
+                if receiver.has_changed().unwrap_or(false) {
+                    let progress = receiver.borrow_and_update().clone();
+                    report(progress).await;
+                }
+            }
+        }
+    }
+}
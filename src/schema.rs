@@ -1,5 +1,97 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    blobs (hash) {
+        hash -> Text,
+        path -> Text,
+        size -> BigInt,
+        refcount -> Integer,
+    }
+}
+
+diesel::table! {
+    collection_objects (collection_id, object_id) {
+        collection_id -> Integer,
+        object_id -> Integer,
+    }
+}
+
+diesel::table! {
+    collection_shares (collection_id, user) {
+        collection_id -> Integer,
+        user -> BigInt,
+        access -> Text,
+        created_unix -> BigInt,
+    }
+}
+
+diesel::table! {
+    collections (id) {
+        id -> Integer,
+        user -> BigInt,
+        name -> Text,
+        source_url -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    destinations (id) {
+        id -> Integer,
+        user -> BigInt,
+        name -> Text,
+        json -> Text,
+        is_default -> Bool,
+        created_unix -> BigInt,
+    }
+}
+
+diesel::table! {
+    jobs (id) {
+        id -> Integer,
+        user -> BigInt,
+        kind -> Text,
+        spec -> Text,
+        status -> Text,
+        output -> Nullable<Text>,
+        error -> Nullable<Text>,
+        created_unix -> BigInt,
+        updated_unix -> BigInt,
+    }
+}
+
+diesel::table! {
+    object_metadata (object_id) {
+        object_id -> Integer,
+        title -> Nullable<Text>,
+        uploader -> Nullable<Text>,
+        upload_date -> Nullable<Text>,
+        duration -> Nullable<Double>,
+        description -> Nullable<Text>,
+        original_url -> Nullable<Text>,
+        extractor -> Nullable<Text>,
+        width -> Nullable<Integer>,
+        height -> Nullable<Integer>,
+        vcodec -> Nullable<Text>,
+        acodec -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    object_shares (object_id, user) {
+        object_id -> Integer,
+        user -> BigInt,
+        access -> Text,
+        created_unix -> BigInt,
+    }
+}
+
+diesel::table! {
+    object_tags (object_id, tag) {
+        object_id -> Integer,
+        tag -> Text,
+    }
+}
+
 diesel::table! {
     objects (id) {
         id -> Integer,
@@ -8,13 +100,31 @@ diesel::table! {
         size -> BigInt,
         expiry_unix -> BigInt,
         user -> BigInt,
+        blob_hash -> Nullable<Text>,
+        source_url -> Nullable<Text>,
+        extractor_id -> Nullable<Text>,
+        created_unix -> BigInt,
     }
 }
 
 diesel::table! {
-    sharex_config (user_id) {
-        user_id -> BigInt,
-        json -> Text,
+    retention_limits (role) {
+        role -> Text,
+        max_days -> Nullable<Integer>,
+    }
+}
+
+diesel::table! {
+    uploads (id) {
+        id -> Integer,
+        object_id -> Nullable<Integer>,
+        object_name -> Text,
+        user -> BigInt,
+        destination_name -> Text,
+        url -> Text,
+        deletion_url -> Nullable<Text>,
+        created_unix -> BigInt,
+        deleted_unix -> Nullable<BigInt>,
     }
 }
 
@@ -22,13 +132,36 @@ diesel::table! {
     users (snowflake) {
         snowflake -> BigInt,
         name_cached -> Nullable<Text>,
+        role -> Text,
+        bytes_used -> BigInt,
+        bytes_allowed -> Nullable<BigInt>,
+        object_count -> Integer,
     }
 }
 
-diesel::joinable!(sharex_config -> users (user_id));
+diesel::joinable!(collection_objects -> collections (collection_id));
+diesel::joinable!(collection_objects -> objects (object_id));
+diesel::joinable!(collection_shares -> collections (collection_id));
+diesel::joinable!(destinations -> users (user));
+diesel::joinable!(object_metadata -> objects (object_id));
+diesel::joinable!(object_shares -> objects (object_id));
+diesel::joinable!(object_tags -> objects (object_id));
+diesel::joinable!(objects -> blobs (blob_hash));
+diesel::joinable!(uploads -> objects (object_id));
+diesel::joinable!(uploads -> users (user));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    blobs,
+    collection_objects,
+    collection_shares,
+    collections,
+    destinations,
+    jobs,
+    object_metadata,
+    object_shares,
+    object_tags,
     objects,
-    sharex_config,
+    retention_limits,
+    uploads,
     users,
 );
@@ -0,0 +1,142 @@
+use diesel::{
+    dsl::sql,
+    prelude::*,
+    sql_types::{Bool, Text},
+};
+
+use crate::db::{self, Object};
+
+const MAX_RESULTS: i64 = 25;
+
+#[derive(poise::ChoiceParameter, Clone, Copy)]
+pub enum MediaType {
+    #[name = "Video"]
+    Video,
+    #[name = "Audio"]
+    Audio,
+    #[name = "Image"]
+    Image,
+    #[name = "Other"]
+    Other,
+}
+
+impl MediaType {
+    fn matches(&self, path: &str) -> bool {
+        let mime = mime_guess::from_path(path).first_or_octet_stream();
+        match self {
+            MediaType::Video => mime.type_() == mime_guess::mime::VIDEO,
+            MediaType::Audio => mime.type_() == mime_guess::mime::AUDIO,
+            MediaType::Image => mime.type_() == mime_guess::mime::IMAGE,
+            MediaType::Other => ![
+                mime_guess::mime::VIDEO,
+                mime_guess::mime::AUDIO,
+                mime_guess::mime::IMAGE,
+            ]
+            .contains(&mime.type_()),
+        }
+    }
+}
+
+pub struct SearchFilters {
+    pub user: i64,
+    pub text: Option<String>,
+    pub domain: Option<String>,
+    pub media_type: Option<MediaType>,
+    pub min_bytes: Option<i64>,
+    pub max_bytes: Option<i64>,
+    pub created_after: Option<i64>,
+    pub created_before: Option<i64>,
+}
+
+// Quotes every word so user input can't use FTS5 query syntax, and matches word prefixes
+fn fts_query(text: &str) -> Option<String> {
+    let terms = text
+        .split_whitespace()
+        .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+        .collect::<Vec<_>>();
+    (!terms.is_empty()).then(|| terms.join(" "))
+}
+
+// So a domain containing % or _ only matches itself
+fn escape_like(text: String) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+// Parses YYYY-MM-DD into a unix timestamp at midnight UTC
+pub fn parse_date(date: &str) -> Option<i64> {
+    let mut parts = date.trim().splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: i64 = parts.next()?.parse().ok()?;
+    let day: i64 = parts.next()?.parse().ok()?;
+    let leap_year = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days_in_month = match month {
+        2 if leap_year => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        1..=12 => 31,
+        _ => return None,
+    };
+    if !(1..=days_in_month).contains(&day) {
+        return None;
+    }
+    // Days from civil, see http://howardhinnant.github.io/date_algorithms.html
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_index = (month + 9) % 12;
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    Some(days * 60 * 60 * 24)
+}
+
+pub fn search(filters: SearchFilters, conn: &mut SqliteConnection) -> anyhow::Result<Vec<Object>> {
+    use crate::schema::objects::dsl::*;
+    let now = db::unix_now();
+    let mut query = objects
+        .filter(user.eq(filters.user))
+        .filter(expiry_unix.gt(now))
+        .select(Object::as_select())
+        .into_boxed();
+    if let Some(text) = filters.text.as_deref().and_then(fts_query) {
+        // A subquery so SQLite combines it with the user filter instead of binding every match
+        query = query.filter(
+            sql::<Bool>("objects.id IN (SELECT rowid FROM objects_fts WHERE objects_fts MATCH ")
+                .bind::<Text, _>(text)
+                .sql(")"),
+        );
+    }
+    if let Some(domain) = filters.domain {
+        // Source URLs are canonicalized, so they're always https and without www.
+        let domain = escape_like(domain.trim().trim_start_matches("www.").to_lowercase());
+        query = query.filter(
+            source_url
+                .like(format!("https://{domain}/%"))
+                .escape('\\')
+                .or(source_url.like(format!("https://%.{domain}/%")).escape('\\')),
+        );
+    }
+    if let Some(min_bytes) = filters.min_bytes {
+        query = query.filter(size.ge(min_bytes));
+    }
+    if let Some(max_bytes) = filters.max_bytes {
+        query = query.filter(size.le(max_bytes));
+    }
+    if let Some(after) = filters.created_after {
+        query = query.filter(created_unix.ge(after));
+    }
+    if let Some(before) = filters.created_before {
+        query = query.filter(created_unix.lt(before));
+    }
+    let query = query.order_by(created_unix.desc());
+    // Media type is derived from the file extension, which SQL can't see through
+    let Some(media_type) = filters.media_type else {
+        return Ok(query.limit(MAX_RESULTS).load(conn)?);
+    };
+    let mut results = query.load(conn)?;
+    results.retain(|object| media_type.matches(&object.path));
+    results.truncate(MAX_RESULTS as usize);
+    Ok(results)
+}
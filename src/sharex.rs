@@ -1,24 +1,767 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    sync::Arc,
+};
+
+use reqwest::{
+    dns::{Addrs, Name, Resolve, Resolving},
+    header::{CONTENT_LENGTH, CONTENT_TYPE, HeaderMap},
+    multipart::{Form, Part},
+    redirect,
+};
 use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::credentials::Credentials;
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum BodyType {
+    None,
+    MultipartFormData,
+    FormURLEncoded,
+    #[serde(rename = "JSON")]
+    Json,
+    #[serde(rename = "XML")]
+    Xml,
+    Binary,
+}
+
+// A ShareX custom uploader, as found in .sxcu files
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct XBackboneShareXData {
+#[serde(rename_all = "PascalCase")]
+pub struct CustomUploader {
+    pub version: Option<String>,
+    pub name: Option<String>,
+    pub destination_type: Option<String>,
+    // Uploaders exported by ShareX before 13.0 call this RequestType
+    #[serde(alias = "RequestType")]
+    pub request_method: Option<String>,
     #[serde(rename = "RequestURL")]
     pub request_url: String,
-    #[serde(rename = "FileFormName")]
-    pub file_form_name: String,
-    #[serde(rename = "Arguments")]
-    pub arguments: Arguments,
+    #[serde(default)]
+    pub parameters: HashMap<String, String>,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    pub body: Option<BodyType>,
+    #[serde(default)]
+    pub arguments: HashMap<String, String>,
+    pub file_form_name: Option<String>,
+    pub data: Option<String>,
+    #[serde(rename = "URL")]
+    pub url: Option<String>,
+    #[serde(rename = "ThumbnailURL")]
+    pub thumbnail_url: Option<String>,
+    #[serde(rename = "DeletionURL")]
+    pub deletion_url: Option<String>,
+    pub error_message: Option<String>,
 }
 
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct Arguments {
-    pub token: String,
+pub struct UploadResult {
+    pub url: String,
+    pub thumbnail_url: Option<String>,
+    pub deletion_url: Option<String>,
 }
 
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-pub struct ReturnedData {
-    #[serde(rename="url")]
-    pub url: String,
+// What response syntax like {json:...} is evaluated against
+pub struct UploadResponse<'a> {
+    pub body: &'a str,
+    pub url: &'a str,
+    pub headers: &'a HeaderMap,
+}
+
+impl CustomUploader {
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        let uploader: Self = serde_json::from_str(json)?;
+        anyhow::ensure!(!uploader.request_url.trim().is_empty(), "RequestURL is empty");
+        reqwest::Method::from_bytes(uploader.method().as_bytes())
+            .map_err(|_| anyhow::anyhow!("Unknown RequestMethod {}", uploader.method()))?;
+        Ok(uploader)
+    }
+
+    // Saved configs have their secrets encrypted, see credentials.rs
+    pub fn from_stored(json: &str, credentials: &Credentials) -> anyhow::Result<Self> {
+        let mut config: serde_json::Value = serde_json::from_str(json)?;
+        map_secrets(&mut config, |secret| credentials.decrypt(secret))?;
+        Self::from_json(&config.to_string())
+    }
+
+    // Stricter than from_json, for configs that are being added rather than ones already saved
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let url = url::Url::parse(&self.request_url.replace("{filename}", "file"))
+            .map_err(|e| anyhow::anyhow!("RequestURL is not a valid URL: {}", e))?;
+        anyhow::ensure!(
+            url.scheme() == "http" || url.scheme() == "https",
+            "RequestURL has to be an http or https URL"
+        );
+        check_public_url(url.as_str()).map_err(|e| anyhow::anyhow!("RequestURL: {}", e))?;
+        if self.body_type() == BodyType::MultipartFormData {
+            anyhow::ensure!(
+                self.file_form_name.as_deref().is_some_and(|name| !name.trim().is_empty()),
+                "FileFormName is required for MultipartFormData uploaders"
+            );
+        }
+        for (field, template) in self.response_templates() {
+            anyhow::ensure!(
+                balanced_braces(template),
+                "{} has an unclosed {{ or }}",
+                field
+            );
+        }
+        Ok(())
+    }
+
+    // Things that are allowed but probably not what the user wants
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = vec![];
+        let destination_types = self
+            .destination_type
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|destination_type| !destination_type.is_empty())
+            .collect::<Vec<_>>();
+        let uploads_files = destination_types.is_empty()
+            || destination_types
+                .iter()
+                .any(|destination_type| FILE_DESTINATION_TYPES.contains(destination_type));
+        if !uploads_files {
+            warnings.push(format!(
+                "DestinationType is {}, this uploader might not accept files",
+                destination_types.join(", ")
+            ));
+        }
+        match self.body_type() {
+            BodyType::None | BodyType::FormURLEncoded | BodyType::Json | BodyType::Xml => {
+                warnings.push(format!(
+                    "Body is {:?}, so the file itself won't be sent",
+                    self.body_type()
+                ))
+            }
+            BodyType::MultipartFormData | BodyType::Binary => {}
+        }
+        if self.url.as_deref().is_none_or(|url| url.trim().is_empty()) {
+            warnings.push("There's no URL, the whole response will be used as the link".to_owned());
+        }
+        for (field, template) in self.response_templates() {
+            for keyword in unknown_keywords(template) {
+                warnings.push(format!("{field} uses {{{keyword}}}, which isn't supported"));
+            }
+        }
+        warnings
+    }
+
+    fn response_templates(&self) -> Vec<(&'static str, &str)> {
+        [
+            ("URL", &self.url),
+            ("ThumbnailURL", &self.thumbnail_url),
+            ("DeletionURL", &self.deletion_url),
+            ("ErrorMessage", &self.error_message),
+        ]
+        .into_iter()
+        .filter_map(|(field, template)| Some((field, template.as_deref()?)))
+        .collect()
+    }
+
+    pub fn method(&self) -> String {
+        self.request_method
+            .as_deref()
+            .unwrap_or("POST")
+            .to_uppercase()
+    }
+
+    // Old uploaders have no Body and always send the file as multipart form data
+    pub fn body_type(&self) -> BodyType {
+        match (self.body, &self.file_form_name) {
+            (Some(body), _) => body,
+            (None, Some(_)) => BodyType::MultipartFormData,
+            (None, None) => BodyType::None,
+        }
+    }
+
+    pub async fn upload(&self, path: &Path, file_name: &str) -> anyhow::Result<UploadResult> {
+        // Of the input syntax only {filename} means anything outside of ShareX
+        let input = |value: &str| value.replace("{filename}", file_name);
+        let method = reqwest::Method::from_bytes(self.method().as_bytes())?;
+        let parameters = self
+            .parameters
+            .iter()
+            .map(|(name, value)| (name.clone(), input(value)))
+            .collect::<Vec<_>>();
+        let arguments = self
+            .arguments
+            .iter()
+            .map(|(name, value)| (name.clone(), input(value)))
+            .collect::<Vec<_>>();
+        let request_url = input(&self.request_url);
+        check_public_url(&request_url)?;
+        let mut request = client()?
+            .request(method, request_url)
+            .query(&parameters);
+        for (name, value) in &self.headers {
+            request = request.header(name, input(value));
+        }
+        let mime = mime_guess::from_path(path).first_or_octet_stream();
+        request = match self.body_type() {
+            BodyType::None => request,
+            BodyType::MultipartFormData => {
+                let mut form = Form::new();
+                for (name, value) in arguments {
+                    form = form.text(name, value);
+                }
+                let file = Part::file(path)
+                    .await?
+                    .file_name(file_name.to_owned())
+                    .mime_str(mime.as_ref())?;
+                let field = self.file_form_name.clone().unwrap_or("file".to_owned());
+                request.multipart(form.part(field, file))
+            }
+            BodyType::FormURLEncoded => request.form(&arguments),
+            BodyType::Json => {
+                let data = match &self.data {
+                    Some(data) => input(data),
+                    None => serde_json::to_string(&arguments.into_iter().collect::<HashMap<_, _>>())?,
+                };
+                request.header(CONTENT_TYPE, "application/json").body(data)
+            }
+            BodyType::Xml => request
+                .header(CONTENT_TYPE, "application/xml")
+                .body(input(self.data.as_deref().unwrap_or_default())),
+            BodyType::Binary => {
+                let file = tokio::fs::File::open(path).await?;
+                let length = file.metadata().await?.len();
+                request
+                    .header(CONTENT_TYPE, mime.as_ref())
+                    .header(CONTENT_LENGTH, length)
+                    .body(file)
+            }
+        };
+
+        let response = request.send().await?;
+        let status = response.status();
+        let response_url = response.url().to_string();
+        let headers = response.headers().clone();
+        let body = response.text().await?;
+        let response = UploadResponse {
+            body: &body,
+            url: &response_url,
+            headers: &headers,
+        };
+        if !status.is_success() {
+            // Only the uploader's own ErrorMessage is shown, raw responses stay in the log
+            warn!("Upload to {} failed with {}: {}", response_url, status, body);
+            let message = self
+                .error_message
+                .as_deref()
+                .and_then(|template| parse_response(template, &response).ok())
+                .filter(|message| !message.trim().is_empty());
+            match message {
+                Some(message) => anyhow::bail!("Upload failed with {}: {}", status, message),
+                None => anyhow::bail!("Upload failed with {}", status),
+            }
+        }
+        // Without a URL template ShareX uses the whole response
+        let url = match self.url.as_deref().filter(|url| !url.trim().is_empty()) {
+            Some(template) => parse_response(template, &response)?,
+            None => body.trim().to_owned(),
+        };
+        anyhow::ensure!(!url.trim().is_empty(), "The uploader didn't return a URL");
+        // The file is already uploaded at this point, so these can't fail the upload
+        let optional = |field: &str, template: &Option<String>| {
+            let template = template.as_deref().filter(|t| !t.trim().is_empty())?;
+            match parse_response(template, &response) {
+                Ok(value) => Some(value).filter(|value| !value.is_empty()),
+                Err(e) => {
+                    warn!("{} of an upload to {} could not be read: {}", field, url, e);
+                    None
+                }
+            }
+        };
+        Ok(UploadResult {
+            thumbnail_url: optional("ThumbnailURL", &self.thumbnail_url),
+            deletion_url: optional("DeletionURL", &self.deletion_url),
+            url,
+        })
+    }
+}
+
+const SECRET_FIELDS: [&str; 2] = ["RequestURL", "Data"];
+const SECRET_MAPS: [&str; 3] = ["Parameters", "Headers", "Arguments"];
+
+// Tokens end up in the request URL, parameters, headers, form arguments or the request body.
+// Works on the JSON as written so fields this bot doesn't know about are kept. Returns whether
+// anything changed
+pub fn map_secrets(
+    config: &mut serde_json::Value,
+    mut f: impl FnMut(&str) -> anyhow::Result<String>,
+) -> anyhow::Result<bool> {
+    let Some(config) = config.as_object_mut() else {
+        anyhow::bail!("The config is not a JSON object");
+    };
+    let mut changed = false;
+    let mut map = |value: &mut serde_json::Value| -> anyhow::Result<()> {
+        if let serde_json::Value::String(text) = value {
+            let mapped = f(text)?;
+            if mapped != *text {
+                *text = mapped;
+                changed = true;
+            }
+        }
+        Ok(())
+    };
+    for field in SECRET_FIELDS {
+        if let Some(value) = config.get_mut(field) {
+            map(value)?;
+        }
+    }
+    for field in SECRET_MAPS {
+        if let Some(serde_json::Value::Object(values)) = config.get_mut(field) {
+            for value in values.values_mut() {
+                map(value)?;
+            }
+        }
+    }
+    Ok(changed)
+}
+
+// The config to store for a newly added uploader
+pub fn seal(json: &str, credentials: &Credentials) -> anyhow::Result<String> {
+    let mut config: serde_json::Value = serde_json::from_str(json)?;
+    map_secrets(&mut config, |secret| credentials.encrypt(secret))?;
+    Ok(config.to_string())
+}
+
+// A transparent 1x1 PNG, uploaded when testing a destination
+pub const TEST_IMAGE: [u8; 68] = [
+    0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48, 0x44, 0x52,
+    0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x08, 0x06, 0x00, 0x00, 0x00, 0x1f, 0x15, 0xc4,
+    0x89, 0x00, 0x00, 0x00, 0x0b, 0x49, 0x44, 0x41, 0x54, 0x78, 0x9c, 0x63, 0x60, 0x00, 0x02, 0x00,
+    0x00, 0x05, 0x00, 0x01, 0x7a, 0x5e, 0xab, 0x3f, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4e, 0x44,
+    0xae, 0x42, 0x60, 0x82,
+];
+
+// ShareX opens deletion URLs in a browser, so a plain GET is what hosts expect
+pub async fn delete_remote(deletion_url: &str) -> anyhow::Result<()> {
+    check_public_url(deletion_url)?;
+    client()?.get(deletion_url).send().await?.error_for_status()?;
+    Ok(())
+}
+
+// Uploader configs come from users, so requests must not reach the bot's own network.
+// Hostnames are checked when they resolve, IP literals skip DNS and are checked here
+pub fn check_public_url(url: &str) -> anyhow::Result<()> {
+    let url = url::Url::parse(url)?;
+    anyhow::ensure!(
+        url.scheme() == "http" || url.scheme() == "https",
+        "Only http and https URLs can be used"
+    );
+    let ip = match url.host() {
+        Some(url::Host::Ipv4(ip)) => IpAddr::V4(ip),
+        Some(url::Host::Ipv6(ip)) => IpAddr::V6(ip),
+        Some(url::Host::Domain(_)) => return Ok(()),
+        None => anyhow::bail!("The URL has no host"),
+    };
+    anyhow::ensure!(is_public(ip), "{} is not a public address", ip);
+    Ok(())
+}
+
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                // Carrier-grade NAT, 100.64.0.0/10
+                || (a == 100 && (64..128).contains(&b))
+                || a == 0
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local())
+            }
+        },
+    }
+}
+
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let host = name.as_str().to_owned();
+            let addresses = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .filter(|address| is_public(address.ip()))
+                .collect::<Vec<SocketAddr>>();
+            if addresses.is_empty() {
+                return Err(format!("{host} does not resolve to a public address").into());
+            }
+            Ok(Box::new(addresses.into_iter()) as Addrs)
+        })
+    }
+}
+
+fn client() -> anyhow::Result<reqwest::Client> {
+    let redirects = redirect::Policy::custom(|attempt| {
+        if attempt.previous().len() >= 10 {
+            attempt.error("Too many redirects")
+        } else if let Err(e) = check_public_url(attempt.url().as_str()) {
+            attempt.error(e.to_string())
+        } else {
+            attempt.follow()
+        }
+    });
+    Ok(reqwest::Client::builder()
+        .dns_resolver(Arc::new(PublicResolver))
+        .redirect(redirects)
+        .build()?)
+}
+
+const FILE_DESTINATION_TYPES: [&str; 2] = ["ImageUploader", "FileUploader"];
+
+const KEYWORDS: [&str; 6] = ["response", "responseurl", "header", "json", "xml", "regex"];
+
+fn balanced_braces(template: &str) -> bool {
+    let chars = template.chars().collect::<Vec<_>>();
+    let mut depth = 0;
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '{' => depth += 1,
+            '}' if depth == 0 => return false,
+            '}' => depth -= 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    depth == 0
+}
+
+// Top level {keyword...} syntax that parse_response would leave as-is
+fn unknown_keywords(template: &str) -> Vec<String> {
+    let chars = template.chars().collect::<Vec<_>>();
+    let mut unknown = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '{' => {
+                if let Some(end) = closing_brace(&chars, i) {
+                    let syntax = chars[i + 1..end].iter().collect::<String>();
+                    let keyword = syntax.split(':').next().unwrap_or_default().trim().to_lowercase();
+                    if !KEYWORDS.contains(&keyword.as_str()) {
+                        unknown.push(keyword);
+                    }
+                    i = end;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    unknown
+}
+
+// Characters that can be escaped with a backslash. Other backslashes are kept so regex
+// patterns like \d work as written
+fn is_escapable(c: char) -> bool {
+    matches!(c, '{' | '}' | '|' | '$' | '\\')
+}
+
+fn unescape(text: &str) -> String {
+    let mut output = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\\', Some(&next)) if is_escapable(next) && next != '\\' => {
+                output.push(next);
+                chars.next();
+            }
+            _ => output.push(c),
+        }
+    }
+    output
+}
+
+// Index of the brace closing the one at `start`, skipping nested and escaped braces
+fn closing_brace(chars: &[char], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+// Fills in the response syntax of URL, ThumbnailURL, DeletionURL and ErrorMessage templates.
+// Supports {response}, {responseurl}, {header:name}, {json:path}, {xml:xpath} and
+// {regex:pattern|group}, and the $json:path$ style used before ShareX 13.0
+pub fn parse_response(template: &str, response: &UploadResponse) -> anyhow::Result<String> {
+    let chars = template.chars().collect::<Vec<_>>();
+    let mut output = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if chars.get(i + 1).is_some_and(|&next| is_escapable(next)) => {
+                output.push(chars[i + 1]);
+                i += 2;
+            }
+            '{' => match closing_brace(&chars, i) {
+                Some(end) => {
+                    let syntax = chars[i + 1..end].iter().collect::<String>();
+                    match evaluate(&syntax, response)? {
+                        Some(value) => output.push_str(&value),
+                        None => output.extend(&chars[i..=end]),
+                    }
+                    i = end + 1;
+                }
+                None => {
+                    output.push('{');
+                    i += 1;
+                }
+            },
+            '$' => {
+                let end = chars[i + 1..].iter().position(|&c| c == '$').map(|end| i + 1 + end);
+                let syntax = end.map(|end| chars[i + 1..end].iter().collect::<String>());
+                match (end, syntax.as_deref().map(|syntax| evaluate(syntax, response))) {
+                    (Some(end), Some(Ok(Some(value)))) => {
+                        output.push_str(&value);
+                        i = end + 1;
+                    }
+                    // A literal dollar sign
+                    _ => {
+                        output.push('$');
+                        i += 1;
+                    }
+                }
+            }
+            c => {
+                output.push(c);
+                i += 1;
+            }
+        }
+    }
+    Ok(output)
+}
+
+// None if this isn't syntax we know, so it's left in the output as-is
+fn evaluate(syntax: &str, response: &UploadResponse) -> anyhow::Result<Option<String>> {
+    let (keyword, argument) = match syntax.split_once(':') {
+        Some((keyword, argument)) => (keyword, Some(argument)),
+        None => (syntax, None),
+    };
+    let value = match (keyword.trim().to_lowercase().as_str(), argument) {
+        ("response", None) => response.body.to_owned(),
+        ("responseurl", None) => response.url.to_owned(),
+        ("header", Some(name)) => response
+            .headers
+            .get(unescape(name).trim())
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_owned(),
+        ("json", Some(path)) => {
+            let json: serde_json::Value = serde_json::from_str(response.body)
+                .map_err(|e| anyhow::anyhow!("The response is not JSON: {}", e))?;
+            json_path(&json, &unescape(path))
+                .ok_or_else(|| anyhow::anyhow!("{} was not found in the response", path))?
+        }
+        ("xml", Some(xpath)) => xml_path(response.body, &unescape(xpath))?,
+        ("regex", Some(argument)) => regex_match(response.body, argument)?,
+        _ => return Ok(None),
+    };
+    Ok(Some(value))
+}
+
+// JSONPath as used by ShareX, e.g. "data.link", "files[0].url" or "$['data']['link']"
+pub fn json_path(json: &serde_json::Value, path: &str) -> Option<String> {
+    let mut current = json;
+    let path = path.trim().trim_start_matches('$');
+    let chars = path.chars().collect::<Vec<_>>();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '.' => i += 1,
+            '[' => {
+                let end = i + chars[i..].iter().position(|&c| c == ']')?;
+                let inside = chars[i + 1..end].iter().collect::<String>();
+                let inside = inside.trim();
+                current = match inside.parse::<usize>() {
+                    Ok(index) => current.get(index)?,
+                    Err(_) => current.get(inside.trim_matches(|c| c == '\'' || c == '"'))?,
+                };
+                i = end + 1;
+            }
+            _ => {
+                let end = chars[i..]
+                    .iter()
+                    .position(|&c| c == '.' || c == '[')
+                    .map_or(chars.len(), |end| i + end);
+                let key = chars[i..end].iter().collect::<String>();
+                current = current.get(key.as_str())?;
+                i = end;
+            }
+        }
+    }
+    match current {
+        serde_json::Value::Null => None,
+        serde_json::Value::String(value) => Some(value.clone()),
+        value => Some(value.to_string()),
+    }
+}
+
+fn xml_path(body: &str, xpath: &str) -> anyhow::Result<String> {
+    let package = sxd_document::parser::parse(body)
+        .map_err(|e| anyhow::anyhow!("The response is not XML: {:?}", e))?;
+    let document = package.as_document();
+    let value = sxd_xpath::evaluate_xpath(&document, xpath.trim())
+        .map_err(|e| anyhow::anyhow!("Invalid XPath {}: {:?}", xpath, e))?;
+    Ok(value.string())
+}
+
+// "pattern|group", where group is an index or a name. Without a group the whole match is used
+fn regex_match(body: &str, argument: &str) -> anyhow::Result<String> {
+    let (pattern, group) = match argument.rsplit_once('|') {
+        Some((pattern, group))
+            if !pattern.ends_with('\\')
+                && !group.is_empty()
+                && group.chars().all(|c| c.is_alphanumeric() || c == '_') =>
+        {
+            (pattern, Some(group))
+        }
+        _ => (argument, None),
+    };
+    let regex = regex::Regex::new(&unescape(pattern))?;
+    let Some(captures) = regex.captures(body) else {
+        return Ok(String::new());
+    };
+    let matched = match group {
+        Some(group) => match group.parse::<usize>() {
+            Ok(index) => captures.get(index),
+            Err(_) => captures.name(group),
+        },
+        None => captures.get(0),
+    };
+    Ok(matched.map(|m| m.as_str().to_owned()).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn parse(template: &str, body: &str) -> anyhow::Result<String> {
+        let mut headers = HeaderMap::new();
+        headers.insert("location", HeaderValue::from_static("https://example.com/moved"));
+        let response = UploadResponse {
+            body,
+            url: "https://example.com/upload",
+            headers: &headers,
+        };
+        parse_response(template, &response)
+    }
+
+    #[test]
+    fn response_and_headers() {
+        assert_eq!(parse("{response}", "https://i.example.com/a.png").unwrap(), "https://i.example.com/a.png");
+        assert_eq!(parse("{responseurl}", "").unwrap(), "https://example.com/upload");
+        assert_eq!(parse("{header:Location}", "").unwrap(), "https://example.com/moved");
+        assert_eq!(parse("{header:missing}", "").unwrap(), "");
+    }
+
+    #[test]
+    fn json() {
+        let body = r#"{"data": {"link": "https://i.example.com/a.png", "files": [{"url": "x"}, {"url": "y"}], "id": 5}}"#;
+        assert_eq!(parse("{json:data.link}", body).unwrap(), "https://i.example.com/a.png");
+        assert_eq!(parse("{json:data.files[1].url}", body).unwrap(), "y");
+        assert_eq!(parse("{json:$['data']['id']}", body).unwrap(), "5");
+        assert_eq!(parse("https://example.com/{json:data.id}.png", body).unwrap(), "https://example.com/5.png");
+        assert!(parse("{json:data.missing}", body).is_err());
+        assert!(parse("{json:data}", "not json").is_err());
+    }
+
+    #[test]
+    fn json_path_edge_cases() {
+        let json = serde_json::json!({"a": {"b": null, "c": [1, 2]}, "d.e": "dotted"});
+        assert_eq!(json_path(&json, "a.b"), None);
+        assert_eq!(json_path(&json, "a.c[0]"), Some("1".to_owned()));
+        assert_eq!(json_path(&json, "a.c[5]"), None);
+        assert_eq!(json_path(&json, "a.c"), Some("[1,2]".to_owned()));
+        assert_eq!(json_path(&json, "['d.e']"), Some("dotted".to_owned()));
+        assert_eq!(json_path(&json, "a.c[0"), None);
+    }
+
+    #[test]
+    fn legacy_dollar_syntax() {
+        let body = r#"{"url": "https://i.example.com/a.png"}"#;
+        assert_eq!(parse("$json:url$", body).unwrap(), "https://i.example.com/a.png");
+        assert_eq!(parse("costs $5", body).unwrap(), "costs $5");
+        assert_eq!(parse("$unknown$", body).unwrap(), "$unknown$");
+    }
+
+    #[test]
+    fn escapes_and_unknown_syntax() {
+        assert_eq!(parse("\\{response\\}", "body").unwrap(), "{response}");
+        assert_eq!(parse("a\\$b", "body").unwrap(), "a$b");
+        assert_eq!(parse("{filename}", "body").unwrap(), "{filename}");
+        assert_eq!(parse("{unclosed", "body").unwrap(), "{unclosed");
+        assert_eq!(parse("{json:{response}}", "{}").unwrap_err().to_string(), "{response} was not found in the response");
+    }
+
+    #[test]
+    fn regex() {
+        let body = "File uploaded to https://i.example.com/abc123.png!";
+        assert_eq!(regex_match(body, r"https://\S+\.png").unwrap(), "https://i.example.com/abc123.png");
+        assert_eq!(regex_match(body, r"example\.com/(\w+)\.png|1").unwrap(), "abc123");
+        assert_eq!(regex_match(body, r"com/(?<id>\w+)|id").unwrap(), "abc123");
+        assert_eq!(regex_match(body, r"nothing").unwrap(), "");
+        // An escaped pipe belongs to the pattern instead of separating the group
+        assert_eq!(regex_match("b", r"a\|b").unwrap(), "b");
+        assert!(regex_match(body, "(").is_err());
+        assert_eq!(parse(r"{regex:/(\w+)\.png|1}", body).unwrap(), "abc123");
+    }
+
+    #[test]
+    fn xml() {
+        let body = "<upload><url>https://i.example.com/a.png</url></upload>";
+        assert_eq!(parse("{xml:/upload/url}", body).unwrap(), "https://i.example.com/a.png");
+        assert!(parse("{xml:/upload/url}", "not xml").is_err());
+    }
+
+    #[test]
+    fn public_addresses() {
+        assert!(check_public_url("https://example.com/upload").is_ok());
+        assert!(check_public_url("https://1.1.1.1/upload").is_ok());
+        assert!(check_public_url("http://127.0.0.1:8080/files").is_err());
+        assert!(check_public_url("http://169.254.169.254/latest/meta-data").is_err());
+        assert!(check_public_url("http://10.0.0.1/").is_err());
+        assert!(check_public_url("http://[::1]/").is_err());
+        assert!(check_public_url("http://[::ffff:192.168.1.1]/").is_err());
+        assert!(check_public_url("http://0.0.0.0:8080/").is_err());
+        assert!(check_public_url("file:///etc/passwd").is_err());
+    }
 }
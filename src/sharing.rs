@@ -0,0 +1,193 @@
+use diesel::prelude::*;
+
+use crate::db::{Collection, CollectionShare, Object, ObjectShare, unix_now};
+
+// Ordered so that every level includes the ones below it
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Access {
+    Read,
+    Manage,
+    Owner,
+}
+
+impl Access {
+    fn id(&self) -> &'static str {
+        match self {
+            Access::Read => "read",
+            Access::Manage => "manage",
+            Access::Owner => "owner",
+        }
+    }
+
+    fn from_id(id: &str) -> Option<Self> {
+        [Access::Read, Access::Manage, Access::Owner]
+            .into_iter()
+            .find(|access| access.id() == id)
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Access::Read => "read",
+            Access::Manage => "read and manage",
+            Access::Owner => "owner",
+        }
+    }
+
+    // What to tell a user who needs this level but doesn't have it, `kind` is "object" or
+    // "collection"
+    pub fn denied_message(&self, kind: &str) -> String {
+        match self {
+            Access::Read => format!("You don't have access to this {kind}"),
+            Access::Manage => format!("You need manage access to this {kind} to do that"),
+            Access::Owner => format!("Only the owner of this {kind} can do that"),
+        }
+    }
+}
+
+#[derive(poise::ChoiceParameter, Clone, Copy)]
+pub enum ShareAccess {
+    #[name = "Read"]
+    Read,
+    #[name = "Read and manage"]
+    Manage,
+}
+
+impl From<ShareAccess> for Access {
+    fn from(access: ShareAccess) -> Self {
+        match access {
+            ShareAccess::Read => Access::Read,
+            ShareAccess::Manage => Access::Manage,
+        }
+    }
+}
+
+fn highest(levels: Vec<String>) -> Option<Access> {
+    levels.iter().filter_map(|level| Access::from_id(level)).max()
+}
+
+// Objects can be shared directly or through any collection they're in, the highest grant wins
+pub fn object_access(oid: i32, uid: i64, conn: &mut SqliteConnection) -> QueryResult<Option<Access>> {
+    use crate::schema::{collection_objects, collection_shares, object_shares, objects};
+    let owner = objects::table
+        .find(oid)
+        .select(objects::user)
+        .first::<i64>(conn)
+        .optional()?;
+    match owner {
+        None => return Ok(None),
+        Some(owner) if owner == uid => return Ok(Some(Access::Owner)),
+        Some(_) => {}
+    }
+    let mut levels = object_shares::table
+        .filter(object_shares::object_id.eq(oid))
+        .filter(object_shares::user.eq(uid))
+        .select(object_shares::access)
+        .load::<String>(conn)?;
+    let containing = collection_objects::table
+        .filter(collection_objects::object_id.eq(oid))
+        .select(collection_objects::collection_id);
+    levels.extend(
+        collection_shares::table
+            .filter(collection_shares::collection_id.eq_any(containing))
+            .filter(collection_shares::user.eq(uid))
+            .select(collection_shares::access)
+            .load::<String>(conn)?,
+    );
+    Ok(highest(levels))
+}
+
+pub fn collection_access(
+    cid: i32,
+    uid: i64,
+    conn: &mut SqliteConnection,
+) -> QueryResult<Option<Access>> {
+    use crate::schema::{collection_shares, collections};
+    let owner = collections::table
+        .find(cid)
+        .select(collections::user)
+        .first::<i64>(conn)
+        .optional()?;
+    match owner {
+        None => return Ok(None),
+        Some(owner) if owner == uid => return Ok(Some(Access::Owner)),
+        Some(_) => {}
+    }
+    let levels = collection_shares::table
+        .filter(collection_shares::collection_id.eq(cid))
+        .filter(collection_shares::user.eq(uid))
+        .select(collection_shares::access)
+        .load::<String>(conn)?;
+    Ok(highest(levels))
+}
+
+// Sharing again with the same user replaces their previous access
+pub fn share_object(oid: i32, grantee: i64, access: Access, conn: &mut SqliteConnection) -> QueryResult<()> {
+    diesel::replace_into(crate::schema::object_shares::table)
+        .values(ObjectShare {
+            object_id: oid,
+            user: grantee,
+            access: access.id().to_owned(),
+            created_unix: unix_now(),
+        })
+        .execute(conn)?;
+    Ok(())
+}
+
+pub fn share_collection(
+    cid: i32,
+    grantee: i64,
+    access: Access,
+    conn: &mut SqliteConnection,
+) -> QueryResult<()> {
+    diesel::replace_into(crate::schema::collection_shares::table)
+        .values(CollectionShare {
+            collection_id: cid,
+            user: grantee,
+            access: access.id().to_owned(),
+            created_unix: unix_now(),
+        })
+        .execute(conn)?;
+    Ok(())
+}
+
+pub fn unshare_object(oid: i32, grantee: i64, conn: &mut SqliteConnection) -> QueryResult<usize> {
+    use crate::schema::object_shares::dsl::*;
+    diesel::delete(object_shares.find((oid, grantee))).execute(conn)
+}
+
+pub fn unshare_collection(cid: i32, grantee: i64, conn: &mut SqliteConnection) -> QueryResult<usize> {
+    use crate::schema::collection_shares::dsl::*;
+    diesel::delete(collection_shares.find((cid, grantee))).execute(conn)
+}
+
+pub struct SharedWithMe {
+    pub objects: Vec<(Object, Access)>,
+    pub collections: Vec<(Collection, Access)>,
+}
+
+pub fn shared_with(uid: i64, conn: &mut SqliteConnection) -> QueryResult<SharedWithMe> {
+    use crate::schema::{collection_shares, collections, object_shares, objects};
+    let objects = objects::table
+        .inner_join(object_shares::table)
+        .filter(object_shares::user.eq(uid))
+        .filter(objects::expiry_unix.gt(unix_now()))
+        .order_by(object_shares::created_unix.desc())
+        .select((Object::as_select(), object_shares::access))
+        .load::<(Object, String)>(conn)?
+        .into_iter()
+        .filter_map(|(object, access)| Some((object, Access::from_id(&access)?)))
+        .collect();
+    let collections = collections::table
+        .inner_join(collection_shares::table)
+        .filter(collection_shares::user.eq(uid))
+        .order_by(collection_shares::created_unix.desc())
+        .select((Collection::as_select(), collection_shares::access))
+        .load::<(Collection, String)>(conn)?
+        .into_iter()
+        .filter_map(|(collection, access)| Some((collection, Access::from_id(&access)?)))
+        .collect();
+    Ok(SharedWithMe {
+        objects,
+        collections,
+    })
+}
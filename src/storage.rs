@@ -0,0 +1,225 @@
+use std::path::{Path, PathBuf};
+
+use diesel::prelude::*;
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+use crate::db::{Blob, NewObject, Object};
+
+// Layout under STORAGE_DIR:
+//   staging/   in-progress downloads and encodes, wiped on startup
+//   objects/   content-addressed blobs, sharded as objects/<first 2 hex digits>/<sha256>.<ext>
+pub fn root() -> PathBuf {
+    let root = PathBuf::from(std::env::var("STORAGE_DIR").unwrap_or("storage".to_owned()));
+    std::path::absolute(&root).unwrap_or(root)
+}
+
+pub fn staging_dir() -> PathBuf {
+    root().join("staging")
+}
+
+pub fn objects_dir() -> PathBuf {
+    root().join("objects")
+}
+
+fn blob_path(hash: &str, extension: &str) -> PathBuf {
+    objects_dir()
+        .join(&hash[..2])
+        .join(format!("{hash}.{extension}"))
+}
+
+fn hash_file(path: &Path) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+// Staging lives on the same filesystem as the objects directory so storing is a plain rename
+pub fn staging_file(extension: &str) -> std::io::Result<tempfile::TempPath> {
+    let file = tempfile::Builder::new()
+        .suffix(&format!(".{extension}"))
+        .tempfile_in(staging_dir())?;
+    Ok(file.into_temp_path())
+}
+
+pub fn staging_workdir() -> std::io::Result<tempfile::TempDir> {
+    tempfile::tempdir_in(staging_dir())
+}
+
+// Hashes the staged file and either moves it into place as a new blob or, if the same content is
+// already stored, drops it and takes another reference to the existing blob. Runs in a transaction,
+// so the staged file is cleaned up by TempPath if anything fails before the move
+pub fn store(
+    mut object: NewObject,
+    file: tempfile::TempPath,
+    conn: &mut SqliteConnection,
+) -> anyhow::Result<Object> {
+    let extension = file
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_string())
+        .unwrap_or("bin".to_owned());
+    let content_hash = hash_file(&file)?;
+    conn.transaction(|conn| {
+        crate::db::charge_user(object.user, object.size, conn)?;
+        use crate::schema::blobs::dsl::*;
+        let existing = blobs
+            .find(&content_hash)
+            .select(Blob::as_select())
+            .first(conn)
+            .optional()?;
+        let blob = match existing {
+            Some(blob) => {
+                info!("Deduplicated {} against blob {}", object.name, blob.hash);
+                diesel::update(blobs.find(&content_hash))
+                    .set(refcount.eq(refcount + 1))
+                    .execute(conn)?;
+                blob
+            }
+            None => {
+                let destination = blob_path(&content_hash, &extension);
+                let blob = Blob {
+                    hash: content_hash.clone(),
+                    path: destination.to_string_lossy().to_string(),
+                    size: std::fs::metadata(&file)?.len() as i64,
+                    refcount: 1,
+                };
+                diesel::insert_into(blobs).values(&blob).execute(conn)?;
+                std::fs::create_dir_all(destination.parent().unwrap())?;
+                file.persist(&destination)?;
+                blob
+            }
+        };
+        object.path = blob.path;
+        object.blob_hash = Some(blob.hash);
+        let stored = diesel::insert_into(crate::schema::objects::table)
+            .values(&object)
+            .returning(Object::as_returning())
+            .get_result(conn)?;
+        Ok(stored)
+    })
+}
+
+fn probe_writable(dir: &Path) -> anyhow::Result<()> {
+    let probe = tempfile::NamedTempFile::new_in(dir)
+        .map_err(|e| anyhow::anyhow!("{} is not writable: {}", dir.display(), e))?;
+    drop(probe);
+    Ok(())
+}
+
+// Run once on startup, before any jobs are picked up
+pub fn init() -> anyhow::Result<()> {
+    let root = root();
+    std::fs::create_dir_all(objects_dir())?;
+    // Nothing can be in flight yet, so anything left in staging is from a crash
+    if staging_dir().exists() {
+        std::fs::remove_dir_all(staging_dir())?;
+    }
+    std::fs::create_dir_all(staging_dir())?;
+    probe_writable(&staging_dir())?;
+    probe_writable(&objects_dir())?;
+
+    let min_free: u64 = std::env::var("STORAGE_MIN_FREE_BYTES")
+        .ok()
+        .and_then(|min| min.parse().ok())
+        .unwrap_or(1_000_000_000);
+    let free = fs2::available_space(&root)?;
+    anyhow::ensure!(
+        free >= min_free,
+        "Only {} free in {}, need at least {}",
+        humansize::format_size(free, humansize::DECIMAL),
+        root.display(),
+        humansize::format_size(min_free, humansize::DECIMAL)
+    );
+    info!(
+        "Using storage directory {} ({} free)",
+        root.display(),
+        humansize::format_size(free, humansize::DECIMAL)
+    );
+    Ok(())
+}
+
+// Tests share one storage directory, set before the first of them touches it
+#[cfg(test)]
+pub fn use_test_root() {
+    static ROOT: std::sync::OnceLock<tempfile::TempDir> = std::sync::OnceLock::new();
+    ROOT.get_or_init(|| {
+        let root = tempfile::tempdir().unwrap();
+        // Nothing else in the tests reads or writes STORAGE_DIR
+        unsafe { std::env::set_var("STORAGE_DIR", root.path()) };
+        std::fs::create_dir_all(staging_dir()).unwrap();
+        std::fs::create_dir_all(objects_dir()).unwrap();
+        root
+    });
+}
+
+// Stores the content as a new object of the user, named after the content
+#[cfg(test)]
+pub fn store_test_object(
+    content: &str,
+    user: i64,
+    expiry_unix: i64,
+    conn: &mut SqliteConnection,
+) -> Object {
+    use_test_root();
+    let file = staging_file("txt").unwrap();
+    std::fs::write(&file, content).unwrap();
+    let object = NewObject {
+        path: String::new(),
+        name: content.to_owned(),
+        size: content.len() as i64,
+        expiry_unix,
+        user,
+        blob_hash: None,
+        source_url: None,
+        extractor_id: None,
+        created_unix: crate::db::unix_now(),
+    };
+    store(object, file, conn).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+
+    fn refcount(blob_hash: &str, conn: &mut SqliteConnection) -> Option<i32> {
+        use crate::schema::blobs::dsl::*;
+        blobs
+            .find(blob_hash)
+            .select(refcount)
+            .first(conn)
+            .optional()
+            .unwrap()
+    }
+
+    #[test]
+    fn same_content_shares_a_blob() {
+        let mut conn = db::test_connection();
+        let first = store_test_object("shared content", 1, db::PINNED, &mut conn);
+        let second = store_test_object("shared content", 2, db::PINNED, &mut conn);
+        let other = store_test_object("other content", 1, db::PINNED, &mut conn);
+        assert_ne!(first.id, second.id);
+        assert_eq!(first.blob_hash, second.blob_hash);
+        assert_eq!(first.path, second.path);
+        assert_ne!(first.path, other.path);
+        assert_eq!(refcount(first.blob_hash.as_ref().unwrap(), &mut conn), Some(2));
+        assert_eq!(std::fs::read_to_string(&first.path).unwrap(), "shared content");
+    }
+
+    #[test]
+    fn blob_outlives_all_but_the_last_object() {
+        let mut conn = db::test_connection();
+        let first = store_test_object("deleted content", 1, db::PINNED, &mut conn);
+        let second = store_test_object("deleted content", 2, db::PINNED, &mut conn);
+        let hash = first.blob_hash.clone().unwrap();
+        assert_eq!(db::delete_object(first.id, &mut conn).unwrap(), None);
+        assert_eq!(refcount(&hash, &mut conn), Some(1));
+        assert!(Path::new(&second.path).exists());
+        assert_eq!(
+            db::delete_object(second.id, &mut conn).unwrap(),
+            Some(second.path)
+        );
+        assert_eq!(refcount(&hash, &mut conn), None);
+    }
+}
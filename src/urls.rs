@@ -0,0 +1,168 @@
+use url::Url;
+
+// Query parameters that only track where a link was shared from, on any site
+const TRACKING_PARAMS: &[&str] = &["fbclid", "gclid", "igshid", "mibextid", "ref_src", "ref_url"];
+
+// Parameters like s or t pick the content on plenty of sites, so they're only dropped where
+// they're known to be share tracking. Hosts also match their subdomains
+const HOST_TRACKING_PARAMS: &[(&str, &[&str])] = &[
+    ("youtube.com", &["feature", "pp", "si"]),
+    ("youtu.be", &["feature", "si"]),
+    ("twitter.com", &["s", "t"]),
+    ("x.com", &["s", "t"]),
+    ("open.spotify.com", &["si"]),
+    ("reddit.com", &["ref", "share_id"]),
+    ("tiktok.com", &["_r", "_t"]),
+    ("instagram.com", &["igsh"]),
+];
+
+fn is_tracking_param(host: &str, name: &str) -> bool {
+    if name.starts_with("utm_") || TRACKING_PARAMS.contains(&name) {
+        return true;
+    }
+    HOST_TRACKING_PARAMS.iter().any(|(tracked_host, params)| {
+        (host == *tracked_host || host.ends_with(&format!(".{tracked_host}")))
+            && params.contains(&name)
+    })
+}
+
+fn youtube_id(url: &Url) -> Option<String> {
+    let host = url.host_str()?;
+    let host = host.strip_prefix("www.").unwrap_or(host);
+    let id = match host {
+        "youtu.be" => url.path_segments()?.next()?.to_owned(),
+        "youtube.com" | "m.youtube.com" | "music.youtube.com" | "youtube-nocookie.com" => {
+            let mut segments = url.path_segments()?;
+            match segments.next()? {
+                "watch" => url
+                    .query_pairs()
+                    .find(|(name, _)| name == "v")
+                    .map(|(_, value)| value.to_string())?,
+                "shorts" | "embed" | "live" | "v" => segments.next()?.to_owned(),
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+    (!id.is_empty()).then_some(id)
+}
+
+// Normalizes a URL so different links to the same page compare equal. Unparseable input is
+// returned as-is
+pub fn canonicalize(input: &str) -> String {
+    let Ok(mut url) = Url::parse(input.trim()) else {
+        return input.trim().to_owned();
+    };
+    if let Some(id) = youtube_id(&url) {
+        return format!("https://www.youtube.com/watch?v={id}");
+    }
+    url.set_fragment(None);
+    if url.path().len() > 1 && url.path().ends_with('/') {
+        let path = url.path().trim_end_matches('/').to_owned();
+        url.set_path(&path);
+    }
+    if url.scheme() == "http" {
+        let _ = url.set_scheme("https");
+    }
+    if let Some(host) = url.host_str().and_then(|host| host.strip_prefix("www.")) {
+        let host = host.to_owned();
+        let _ = url.set_host(Some(&host));
+    }
+    let host = url.host_str().unwrap_or_default().to_owned();
+    let mut params = url
+        .query_pairs()
+        .filter(|(name, _)| !is_tracking_param(&host, name))
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect::<Vec<_>>();
+    params.sort();
+    if params.is_empty() {
+        url.set_query(None);
+    } else {
+        url.query_pairs_mut().clear().extend_pairs(params);
+    }
+    url.to_string()
+}
+
+// The "extractor:id" key yt-dlp would report for this URL, where it can be known up front
+pub fn extractor_id(input: &str) -> Option<String> {
+    let url = Url::parse(input.trim()).ok()?;
+    youtube_id(&url).map(|id| format!("Youtube:{id}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn youtube_links_share_one_form() {
+        let canonical = "https://www.youtube.com/watch?v=dQw4w9WgXcQ";
+        for url in [
+            "https://youtu.be/dQw4w9WgXcQ?si=abc",
+            "http://m.youtube.com/watch?v=dQw4w9WgXcQ&feature=share",
+            "https://www.youtube.com/shorts/dQw4w9WgXcQ",
+            "https://www.youtube.com/watch?t=42&v=dQw4w9WgXcQ&pp=xyz",
+        ] {
+            assert_eq!(canonicalize(url), canonical, "{url}");
+        }
+        assert_eq!(extractor_id("https://youtu.be/dQw4w9WgXcQ"), Some("Youtube:dQw4w9WgXcQ".to_owned()));
+    }
+
+    #[test]
+    fn normalizes_scheme_host_path_and_fragment() {
+        assert_eq!(
+            canonicalize("http://www.example.com/page/#comments"),
+            "https://example.com/page"
+        );
+        assert_eq!(canonicalize("https://example.com/"), "https://example.com/");
+        assert_eq!(canonicalize("  not a url "), "not a url");
+    }
+
+    #[test]
+    fn strips_tracking_everywhere() {
+        assert_eq!(
+            canonicalize("https://example.com/a?utm_source=x&id=1&fbclid=y&utm_medium=z"),
+            "https://example.com/a?id=1"
+        );
+        assert_eq!(canonicalize("https://example.com/a?gclid=1"), "https://example.com/a");
+    }
+
+    #[test]
+    fn sorts_params() {
+        assert_eq!(
+            canonicalize("https://example.com/a?b=2&a=1"),
+            "https://example.com/a?a=1&b=2"
+        );
+    }
+
+    #[test]
+    fn keeps_ambiguous_params_on_other_hosts() {
+        assert_eq!(
+            canonicalize("https://example.com/search?s=cats&t=video&ref=main&pp=2&feature=x"),
+            "https://example.com/search?feature=x&pp=2&ref=main&s=cats&t=video"
+        );
+        assert_ne!(
+            canonicalize("https://example.com/?p=1&s=first"),
+            canonicalize("https://example.com/?p=1&s=second")
+        );
+    }
+
+    #[test]
+    fn strips_share_params_on_known_hosts() {
+        assert_eq!(
+            canonicalize("https://x.com/user/status/123?s=20&t=abc"),
+            "https://x.com/user/status/123"
+        );
+        assert_eq!(
+            canonicalize("https://mobile.twitter.com/user/status/123?s=46"),
+            "https://mobile.twitter.com/user/status/123"
+        );
+        assert_eq!(
+            canonicalize("https://www.reddit.com/r/rust/comments/abc/?share_id=x&ref=share"),
+            "https://reddit.com/r/rust/comments/abc"
+        );
+        assert_eq!(
+            canonicalize("https://open.spotify.com/track/abc?si=123"),
+            "https://open.spotify.com/track/abc"
+        );
+    }
+}
@@ -1,30 +1,151 @@
 use poise::serenity_prelude::async_trait;
+use serde::Deserialize;
 use tokio::io::AsyncBufReadExt;
 
-use crate::downloader::Downloader;
+use crate::{
+    db::ObjectMetadata,
+    downloader::{
+        DownloadFailure, DownloadOutput, DownloadedItem, Downloader, SourceMetadata, adopt_file,
+    },
+    progress::{Progress, ProgressReporter},
+};
 
-pub struct YoutubeDownloader {}
+// Multi-part posts outside of playlist mode are capped at this many files
+const MAX_POST_DOWNLOADS: u32 = 4;
+const MAX_DOWNLOADS_REACHED: i32 = 101;
+
+pub struct YoutubeDownloader {
+    pub playlist: bool,
+    pub max_entries: u32,
+    pub max_filesize: Option<u64>,
+}
+
+impl YoutubeDownloader {
+    pub fn playlist_entry_cap() -> u32 {
+        std::env::var("YTDLP_PLAYLIST_MAX_ENTRIES")
+            .ok()
+            .and_then(|cap| cap.parse().ok())
+            .unwrap_or(50)
+    }
+}
+
+#[derive(Deserialize)]
+struct PrintedItem {
+    id: Option<String>,
+    title: Option<String>,
+    filepath: String,
+    webpage_url: Option<String>,
+    extractor_key: Option<String>,
+    playlist_title: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PrintedProgress {
+    status: Option<String>,
+    downloaded_bytes: Option<f64>,
+    total_bytes: Option<f64>,
+    total_bytes_estimate: Option<f64>,
+    speed: Option<f64>,
+    eta: Option<f64>,
+    fragment_index: Option<u64>,
+    fragment_count: Option<u64>,
+}
+
+// Progress lines look like "DLPROGRESS <playlist index> <entry count> <json>", see the template below
+fn parse_progress_line(line: &str) -> Option<Progress> {
+    let mut parts = line.strip_prefix("DLPROGRESS ")?.splitn(3, ' ');
+    let index: u64 = parts.next()?.parse().unwrap_or(0);
+    let entries: u64 = parts.next()?.parse().unwrap_or(0);
+    let printed: PrintedProgress = serde_json::from_str(parts.next()?).ok()?;
+    let total = printed.total_bytes.or(printed.total_bytes_estimate);
+    let stage = match printed.status.as_deref() {
+        Some("finished") => "Processing",
+        _ => "Downloading",
+    };
+    Some(Progress {
+        stage: stage.to_owned(),
+        percent: printed
+            .downloaded_bytes
+            .zip(total)
+            .filter(|(_, total)| *total > 0.)
+            .map(|(downloaded, total)| downloaded / total * 100.),
+        speed: printed
+            .speed
+            .map(|speed| humansize::format_size(speed as u64, humansize::DECIMAL) + "/s"),
+        eta: printed.eta.map(|eta| eta as u64),
+        fragments: printed.fragment_index.zip(printed.fragment_count),
+        entry: (entries > 1).then_some((index, entries)),
+    })
+}
+
+// yt-dlp reports errors as "ERROR: [extractor] id: reason"
+fn parse_error_line(line: &str) -> Option<DownloadFailure> {
+    let error = line.strip_prefix("ERROR: ")?;
+    let failure = error
+        .strip_prefix('[')
+        .and_then(|rest| rest.split_once("] "))
+        .and_then(|(_, rest)| rest.split_once(": "))
+        .map(|(entry, reason)| DownloadFailure {
+            entry: entry.to_owned(),
+            reason: reason.to_owned(),
+        });
+    Some(failure.unwrap_or(DownloadFailure {
+        entry: "unknown".to_owned(),
+        reason: error.to_owned(),
+    }))
+}
+
+// The info json is written next to the download, as <id>.info.json
+fn read_info_json(path: &std::path::Path) -> Option<ObjectMetadata> {
+    let json = std::fs::read_to_string(path).ok()?;
+    match serde_json::from_str(&json) {
+        Ok(metadata) => Some(metadata),
+        Err(e) => {
+            tracing::warn!("Could not parse {}: {}", path.display(), e);
+            None
+        }
+    }
+}
 
 #[async_trait]
 impl Downloader for YoutubeDownloader {
-    async fn download(&self, url: String) -> Result<(String, tempfile::NamedTempFile), anyhow::Error> {
-        let tempfile = tempfile::NamedTempFile::with_suffix(".mp4")?;
+    async fn download(
+        &self,
+        url: String,
+        progress: &ProgressReporter,
+    ) -> Result<DownloadOutput, anyhow::Error> {
+        let tempdir = crate::storage::staging_workdir()?;
         let mut command = tokio::process::Command::new("yt-dlp");
         command
             .arg("-o")
-            .arg(tempfile.path())
+            .arg(tempdir.path().join("%(id)s.%(ext)s"))
             .arg("--recode-video")
             .arg("mp4")
             .arg("--force-overwrite")
-            .arg("--no-playlist")
-            .arg("-I")
-            .arg("1:1")
-            .arg("--max-downloads")
-            .arg("4")
+            .arg("--write-info-json");
+        if self.playlist {
+            command
+                .arg("--yes-playlist")
+                .arg("-I")
+                .arg(format!("1:{}", self.max_entries))
+                .arg("--ignore-errors");
+        } else {
+            command
+                .arg("--no-playlist")
+                .arg("--max-downloads")
+                .arg(MAX_POST_DOWNLOADS.to_string());
+        }
+        command
             .arg("--no-simulate")
             .arg("--progress")
+            .arg("--newline")
+            .arg("--progress-template")
+            .arg("download:DLPROGRESS %(info.playlist_index|0)s %(info.n_entries|0)s %(progress.{status,downloaded_bytes,total_bytes,total_bytes_estimate,speed,eta,fragment_index,fragment_count})j")
             .arg("--print")
-            .arg("VIDEOTITLE((![[%(title)s]]!))");
+            .arg("after_move:VIDEOITEM %(.{id,title,filepath,webpage_url,extractor_key,playlist_title})j");
+        if let Some(max_filesize) = self.max_filesize {
+            command.arg("--max-filesize").arg(max_filesize.to_string());
+        }
         if std::env::var("YTDLP_COOKIES_FILE").is_ok() {
             command.arg("--cookies").arg(std::env::var("YTDLP_COOKIES_FILE").unwrap());
         };
@@ -33,24 +154,69 @@ impl Downloader for YoutubeDownloader {
             .arg(&url);
         // We want to capture stdout but also log it with tracing-appender
         command.stdout(std::process::Stdio::piped());
+        command.kill_on_drop(true);
+        command.stderr(std::process::Stdio::piped());
         let mut child = command.spawn()?;
-        
-        
+
+        let stderr = child.stderr.take().unwrap();
+        let stderr_task = tokio::spawn(async move {
+            let mut lines = tokio::io::BufReader::new(stderr).lines();
+            let mut failures = vec![];
+            while let Ok(Some(line)) = lines.next_line().await {
+                tracing::warn!("{}", line);
+                failures.extend(parse_error_line(&line));
+            }
+            failures
+        });
+
         let stdout = child.stdout.take().unwrap();
         let bufreader = tokio::io::BufReader::new(stdout);
         let mut lines = bufreader.lines();
-        let mut vidtitle = None;
+        let mut printed = vec![];
         while let Ok(Some(line)) = lines.next_line().await {
             tracing::info!("{}", line);
-            if line.starts_with("VIDEOTITLE") {
-                let title = line.split("((![[").nth(1).unwrap();
-                let title = title.split("]]!))").nth(0).unwrap();
-                vidtitle = Some(title.to_owned());
+            if let Some(update) = parse_progress_line(&line) {
+                progress.update(update);
+                continue;
+            }
+            if let Some(json) = line.strip_prefix("VIDEOITEM ") {
+                printed.push(serde_json::from_str::<PrintedItem>(json)?);
+            }
+        }
+        let status = child.wait().await?;
+        let failures = stderr_task.await?;
+        // yt-dlp exits with 101 once --max-downloads is reached, which still leaves those downloads
+        let finished = status.success() || status.code() == Some(MAX_DOWNLOADS_REACHED);
+        if !finished && (!self.playlist || printed.is_empty()) {
+            match failures.first() {
+                Some(failure) => anyhow::bail!("youtube-dl failed: {}", failure.reason),
+                None => anyhow::bail!("youtube-dl failed"),
             }
         }
-        let status = child.wait().await?; 
-        anyhow::ensure!(status.success(), "youtube-dl failed");
 
-        Ok((vidtitle.unwrap_or(url), tempfile))
+        let mut output = DownloadOutput {
+            failures,
+            ..Default::default()
+        };
+        for item in printed {
+            if output.title.is_none() {
+                output.title = item.playlist_title.or(item.title.clone());
+            }
+            let file = adopt_file(std::path::Path::new(&item.filepath))?;
+            let metadata = item.id.as_ref().and_then(|id| {
+                read_info_json(&tempdir.path().join(format!("{id}.info.json")))
+            });
+            let source = SourceMetadata {
+                url: item.webpage_url.unwrap_or(url.clone()),
+                extractor: item.extractor_key,
+                id: item.id,
+            };
+            let mut downloaded =
+                DownloadedItem::new(item.title.unwrap_or(url.clone()), file, source);
+            downloaded.metadata = metadata;
+            output.items.push(downloaded);
+        }
+        anyhow::ensure!(!output.items.is_empty(), "youtube-dl did not produce any files");
+        Ok(output)
     }
 }